@@ -1,5 +1,12 @@
-use std::io::{self, Write};
-
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, ToPrimitive, Zero};
 use ordered_float::OrderedFloat;
 
 use super::values::Value;
@@ -62,46 +69,65 @@ pub fn ash_str(args: Vec<Value>) -> Value {
     let val = args.first().expect("Expected 1 argument 0 found");
     Value::StringValue(match val {
         Value::IntValue(_i) => _i.to_string(),
+        Value::BigIntValue(_i) => _i.to_string(),
+        Value::RationalValue(_r) => _r.to_string(),
         Value::DoubleValue(_d) => _d.0.to_string(),
         Value::StringValue(_s) => _s.to_string(),
         Value::BooleanValue(_b) => _b.to_string(),
         Value::ListValue(_l) => _l
+            .borrow()
             .iter()
             .map(|x| x.to_string())
             .collect::<Vec<String>>()
             .join(""),
+        Value::TupleValue(_)
+        | Value::SetValue(_)
+        | Value::MapValue(_)
+        | Value::InstanceValue(_) => val.to_string(),
         _ => panic!("Invalid Type Conversion"),
     })
 }
 
 pub fn ash_list(args: Vec<Value>) -> Value {
     let val = args.first().expect("Expected 1 argument 0 found");
-    Value::ListValue(match val {
+    Value::list(match val {
         Value::StringValue(_s) => _s
             .chars()
             .map(|x| Value::StringValue(x.to_string()))
             .collect::<Vec<Value>>(),
-        Value::ListValue(_l) => _l.clone(),
+        Value::ListValue(_l) => _l.borrow().clone(),
+        Value::TupleValue(_t) => _t.clone(),
         _ => panic!("Invalid Type Conversion"),
     })
 }
 
 pub fn ash_int(args: Vec<Value>) -> Value {
     let val = args.first().expect("Expected 1 argument 0 found");
-    Value::IntValue(match val {
-        Value::IntValue(_i) => *_i as i64,
-        Value::DoubleValue(_d) => _d.0 as i64,
-        Value::StringValue(_s) => _s.parse::<i64>().expect("int Parsing Error"),
-        Value::BooleanValue(_b) => i64::from(*_b),
+    match val {
+        Value::IntValue(_) | Value::BigIntValue(_) => val.clone(),
+        Value::DoubleValue(_d) => match _d.0.to_i64() {
+            Some(_i) => Value::IntValue(_i),
+            None => Value::BigIntValue(
+                BigInt::from_f64(_d.0.trunc()).expect("Can't convert NaN or Infinity to int"),
+            ),
+        },
+        Value::StringValue(_s) => match _s.parse::<i64>() {
+            Ok(_i) => Value::IntValue(_i),
+            Err(_) => Value::from_big_int(_s.parse::<BigInt>().expect("int Parsing Error")),
+        },
+        Value::RationalValue(_r) => Value::from_big_int(_r.trunc().to_integer()),
+        Value::BooleanValue(_b) => Value::IntValue(i64::from(*_b)),
         _ => panic!("Invalid Type Conversion"),
-    })
+    }
 }
 
 pub fn ash_double(args: Vec<Value>) -> Value {
     let val = args.first().expect("Expected 1 argument 0 found");
     Value::DoubleValue(OrderedFloat(match val {
         Value::IntValue(_i) => *_i as f64,
-        Value::DoubleValue(_d) => _d.0 as f64,
+        Value::BigIntValue(_i) => _i.to_f64().unwrap_or(f64::NAN),
+        Value::RationalValue(_r) => _r.to_f64().unwrap_or(f64::NAN),
+        Value::DoubleValue(_d) => _d.0,
         Value::StringValue(_s) => _s.parse::<f64>().expect("double Parsing Error"),
         Value::BooleanValue(_b) => {
             if *_b {
@@ -114,24 +140,23 @@ pub fn ash_double(args: Vec<Value>) -> Value {
     }))
 }
 
-pub fn ash_min(args: Vec<Value>) -> Value {
-    let mut min = args.first().expect("Expected at least 1 argument found 0");
-    for arg in args.iter() {
-        if arg < min {
-            min = arg
-        }
-    }
-    min.to_owned()
-}
-
-pub fn ash_max(args: Vec<Value>) -> Value {
-    let mut max = args.first().expect("Expected at least 1 argument found 0");
-    for arg in args.iter() {
-        if arg > max {
-            max = arg
-        }
+pub fn ash_frac(args: Vec<Value>) -> Value {
+    let to_rational = |val: &Value| match val {
+        Value::StringValue(_s) => _s
+            .trim()
+            .parse::<BigRational>()
+            .expect("frac Parsing Error"),
+        _ => val.to_rational().expect("Invalid Type Conversion"),
+    };
+    let numer = to_rational(args.first().expect("Expected at least 1 argument found 0"));
+    let denom = args
+        .get(1)
+        .map(to_rational)
+        .unwrap_or_else(BigRational::one);
+    if denom.is_zero() {
+        panic!("frac: denominator can't be zero")
     }
-    max.to_owned()
+    Value::from_rational(numer / denom)
 }
 
 pub fn ash_get(mut args: Vec<Value>) -> Value {
@@ -150,12 +175,22 @@ pub fn ash_get(mut args: Vec<Value>) -> Value {
     match &mut this {
         Value::ListValue(_l) => {
             if let Value::IntValue(idx) = idx_val {
-                _l.remove(idx as usize)
+                _l.borrow()
+                    .get(idx as usize)
+                    .expect("Index out of bounds")
+                    .clone()
+            } else {
+                panic!("Invalid Index")
+            }
+        }
+        Value::TupleValue(_t) => {
+            if let Value::IntValue(idx) = idx_val {
+                _t.remove(idx as usize)
             } else {
                 panic!("Invalid Index")
             }
         }
-        Value::MapValue(_m) => _m.get(&idx_val).expect("Key not found").clone(),
+        Value::MapValue(_m) => _m.borrow().get(&idx_val).expect("Key not found").clone(),
         Value::StringValue(_s) => {
             if let Value::IntValue(idx) = idx_val {
                 Value::StringValue(
@@ -189,24 +224,31 @@ pub fn ash_set(mut args: Vec<Value>) -> Value {
     let mut this = args
         .first_mut()
         .expect("Expected at least 1 argument found 0");
+    check_cycle(this, &val);
 
     match &mut this {
         Value::ListValue(_l) => {
             if let Value::IntValue(idx) = idx_val {
-                _l[(idx as usize)] = val;
+                *_l.borrow_mut()
+                    .get_mut(idx as usize)
+                    .expect("Index out of bounds") = val;
             } else {
                 panic!("Invalid Index")
             }
         }
         Value::MapValue(_m) => {
-            _m.insert(idx_val, val);
+            _m.borrow_mut().insert(idx_val.deep_copy(), val);
         }
+        Value::TupleValue(_) => panic!("Tuples are immutable"),
         Value::StringValue(_s) => {
             if let Value::IntValue(idx) = idx_val {
                 if let Value::StringValue(_val) = val {
-                    *_s = _s.as_str()[..(idx as usize)].to_string()
-                        + &_val
-                        + (&_s.as_str()[(idx as usize + 1)..]);
+                    let mut chars: Vec<char> = _s.chars().collect();
+                    if idx < 0 || idx as usize >= chars.len() {
+                        panic!("Index out of bounds")
+                    }
+                    chars.splice(idx as usize..idx as usize + 1, _val.chars());
+                    *_s = chars.into_iter().collect();
                 } else {
                     panic!("Invalid Value")
                 }
@@ -227,9 +269,11 @@ pub fn ash_len(args: Vec<Value>) -> Value {
     let this = args.first().expect("Expected at least 1 argument found 0");
 
     match this {
-        Value::ListValue(_l) => Value::IntValue(_l.len() as i64),
-        Value::MapValue(_m) => Value::IntValue(_m.len() as i64),
-        Value::StringValue(_s) => Value::IntValue(_s.len() as i64),
+        Value::ListValue(_l) => Value::IntValue(_l.borrow().len() as i64),
+        Value::TupleValue(_t) => Value::IntValue(_t.len() as i64),
+        Value::MapValue(_m) => Value::IntValue(_m.borrow().len() as i64),
+        Value::SetValue(_s) => Value::IntValue(_s.len() as i64),
+        Value::StringValue(_s) => Value::IntValue(_s.chars().count() as i64),
         _ => panic!("Invalid argument"),
     }
 }
@@ -239,6 +283,7 @@ pub fn ash_pop(args: Vec<Value>) -> Value {
         panic!("Invalid arguments")
     }
 
+    // Like set, pop changes Lists and Maps in place and returns them
     let mut this = args
         .first()
         .expect("Expected at least 1 argument found 0")
@@ -248,18 +293,30 @@ pub fn ash_pop(args: Vec<Value>) -> Value {
     match &mut this {
         Value::ListValue(_l) => {
             if let Value::IntValue(idx) = idx_val {
-                *_l = [&_l[..(*idx as usize)], &_l[(*idx as usize + 1)..]].concat();
+                let mut _l = _l.borrow_mut();
+                if *idx < 0 || *idx as usize >= _l.len() {
+                    panic!("Index out of bounds")
+                }
+                _l.remove(*idx as usize);
             } else {
                 panic!("Invalid Index")
             }
         }
         Value::MapValue(_m) => {
-            _m.remove(idx_val);
+            _m.borrow_mut().shift_remove(idx_val);
+        }
+        Value::SetValue(_s) => {
+            _s.remove(idx_val);
         }
+        Value::TupleValue(_) => panic!("Tuples are immutable"),
         Value::StringValue(_s) => {
             if let Value::IntValue(idx) = idx_val {
-                *_s = _s.as_str()[..(*idx as usize)].to_string()
-                    + (&_s.as_str()[(*idx as usize + 1)..]);
+                let mut chars: Vec<char> = _s.chars().collect();
+                if *idx < 0 || *idx as usize >= chars.len() {
+                    panic!("Index out of bounds")
+                }
+                chars.remove(*idx as usize);
+                *_s = chars.into_iter().collect();
             } else {
                 panic!("Invalid Index")
             }
@@ -269,6 +326,40 @@ pub fn ash_pop(args: Vec<Value>) -> Value {
     this
 }
 
+pub fn ash_slice(args: Vec<Value>) -> Value {
+    if args.len() != 2 && args.len() != 3 {
+        panic!("Invalid arguments")
+    }
+
+    let this = args.first().expect("Expected at least 1 argument found 0");
+    let len = match this {
+        Value::ListValue(_l) => _l.borrow().len(),
+        Value::TupleValue(_t) => _t.len(),
+        Value::StringValue(_s) => _s.chars().count(),
+        _ => panic!("Invalid argument"),
+    };
+    let bound = |val: Option<&Value>, default: usize| match val {
+        None => default,
+        Some(Value::IntValue(idx)) if *idx >= 0 && *idx as usize <= len => *idx as usize,
+        Some(Value::IntValue(_)) => panic!("Index out of bounds"),
+        Some(_) => panic!("Invalid Index"),
+    };
+    let start = bound(args.get(1), 0);
+    let end = bound(args.get(2), len);
+    if start > end {
+        panic!("Invalid slice: start {} is after end {}", start, end)
+    }
+
+    match this {
+        Value::ListValue(_l) => Value::list(_l.borrow()[start..end].to_vec()),
+        Value::TupleValue(_t) => Value::TupleValue(_t[start..end].to_vec()),
+        Value::StringValue(_s) => {
+            Value::StringValue(_s.chars().skip(start).take(end - start).collect())
+        }
+        _ => unreachable!(),
+    }
+}
+
 pub fn ash_keys(args: Vec<Value>) -> Value {
     let this = args
         .first()
@@ -278,10 +369,10 @@ pub fn ash_keys(args: Vec<Value>) -> Value {
     match this {
         Value::MapValue(_m) => {
             let mut keys = vec![];
-            for k in _m.keys() {
-                keys.push(k.clone());
+            for k in _m.borrow().keys() {
+                keys.push(k.deep_copy());
             }
-            Value::ListValue(keys)
+            Value::list(keys)
         }
         _ => panic!("Invalid argument"),
     }
@@ -299,22 +390,252 @@ pub fn ash_has(args: Vec<Value>) -> Value {
     let idx_val = args.get(1).expect("Expected value/key as second argument");
 
     match this {
-        Value::ListValue(_l) => {
-            for k in _l {
-                if k == *idx_val {
-                    return Value::BooleanValue(true);
-                }
+        Value::ListValue(_l) => Value::BooleanValue(_l.borrow().contains(idx_val)),
+        Value::TupleValue(_t) => Value::BooleanValue(_t.contains(idx_val)),
+        Value::MapValue(_m) => Value::BooleanValue(_m.borrow().contains_key(idx_val)),
+        Value::SetValue(_s) => Value::BooleanValue(_s.contains(idx_val)),
+        _ => panic!("Invalid argument"),
+    }
+}
+
+pub fn ash_trim(args: Vec<Value>) -> Value {
+    match args.first() {
+        Some(Value::StringValue(_s)) if args.len() == 1 => Value::StringValue(_s.trim().to_owned()),
+        _ => panic!("trim expects a String"),
+    }
+}
+
+// String methods, only reachable through method-call syntax: s.upper()
+pub fn ash_upper(args: Vec<Value>) -> Value {
+    match args.first() {
+        Some(Value::StringValue(_s)) if args.len() == 1 => Value::StringValue(_s.to_uppercase()),
+        _ => panic!("upper expects no arguments"),
+    }
+}
+
+pub fn ash_lower(args: Vec<Value>) -> Value {
+    match args.first() {
+        Some(Value::StringValue(_s)) if args.len() == 1 => Value::StringValue(_s.to_lowercase()),
+        _ => panic!("lower expects no arguments"),
+    }
+}
+
+pub fn ash_starts_with(args: Vec<Value>) -> Value {
+    match (args.first(), args.get(1)) {
+        (Some(Value::StringValue(_s)), Some(Value::StringValue(_p))) if args.len() == 2 => {
+            Value::BooleanValue(_s.starts_with(_p.as_str()))
+        }
+        _ => panic!("starts_with expects a String prefix"),
+    }
+}
+
+pub fn ash_ends_with(args: Vec<Value>) -> Value {
+    match (args.first(), args.get(1)) {
+        (Some(Value::StringValue(_s)), Some(Value::StringValue(_p))) if args.len() == 2 => {
+            Value::BooleanValue(_s.ends_with(_p.as_str()))
+        }
+        _ => panic!("ends_with expects a String suffix"),
+    }
+}
+
+// split(s) splits on whitespace, split(s, sep) on every sep
+pub fn ash_split(args: Vec<Value>) -> Value {
+    let parts: Vec<&str> = match (args.first(), args.get(1)) {
+        (Some(Value::StringValue(_s)), None) => _s.split_whitespace().collect(),
+        (Some(Value::StringValue(_s)), Some(Value::StringValue(_sep))) if !_sep.is_empty() => {
+            _s.split(_sep.as_str()).collect()
+        }
+        _ => panic!("split expects a String and a non-empty String separator"),
+    };
+    Value::list(
+        parts
+            .into_iter()
+            .map(|_p| Value::StringValue(_p.to_owned()))
+            .collect(),
+    )
+}
+
+pub fn ash_join(args: Vec<Value>) -> Value {
+    let sep = match args.get(1) {
+        Some(Value::StringValue(_sep)) => _sep.as_str(),
+        None => "",
+        _ => panic!("join expects a String separator"),
+    };
+    let values = match args.first() {
+        Some(Value::ListValue(_l)) => _l.borrow().clone(),
+        Some(Value::TupleValue(_t)) => _t.clone(),
+        _ => panic!("join expects a List"),
+    };
+    Value::StringValue(
+        values
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(sep),
+    )
+}
+
+// Lists and Maps are shared, these builtins change them in place
+
+// A List or Map holding itself, even through other values, could never be
+// printed, compared or copied
+fn check_cycle(target: &Value, value: &Value) {
+    if value.reaches(target) {
+        panic!("Cannot insert a {} into itself", target.type_name())
+    }
+}
+
+// push(xs, value) appends to a List
+pub fn ash_push(args: Vec<Value>) -> Value {
+    match &args[..] {
+        [Value::ListValue(_l), _val] => {
+            check_cycle(&args[0], _val);
+            _l.borrow_mut().push(_val.clone())
+        }
+        _ => panic!("push expects a List and a value"),
+    }
+    Value::None
+}
+
+// insert(xs, idx, value) shifts the elements from idx, insert(m, key, value) sets the key
+pub fn ash_insert(args: Vec<Value>) -> Value {
+    match &args[..] {
+        [Value::ListValue(_l), Value::IntValue(_idx), _val] => {
+            check_cycle(&args[0], _val);
+            let mut _l = _l.borrow_mut();
+            if *_idx < 0 || *_idx as usize > _l.len() {
+                panic!("Index out of bounds")
             }
-            Value::BooleanValue(false)
+            _l.insert(*_idx as usize, _val.clone());
         }
-        Value::MapValue(_m) => {
-            for k in _m.keys() {
-                if k == idx_val {
-                    return Value::BooleanValue(true);
-                }
+        [Value::MapValue(_m), _key, _val] => {
+            check_cycle(&args[0], _val);
+            _m.borrow_mut().insert(_key.deep_copy(), _val.clone());
+        }
+        _ => panic!("insert expects a List, an Int index and a value or a Map, a key and a value"),
+    }
+    Value::None
+}
+
+// remove(xs, idx) and remove(m, key) return the removed value
+pub fn ash_remove(args: Vec<Value>) -> Value {
+    match &args[..] {
+        [Value::ListValue(_l), Value::IntValue(_idx)] => {
+            let mut _l = _l.borrow_mut();
+            if *_idx < 0 || *_idx as usize >= _l.len() {
+                panic!("Index out of bounds")
             }
-            Value::BooleanValue(false)
+            _l.remove(*_idx as usize)
         }
-        _ => panic!("Invalid argument"),
+        [Value::MapValue(_m), _key] => _m.borrow_mut().shift_remove(_key).expect("Key not found"),
+        _ => panic!("remove expects a List and an Int index or a Map and a key"),
+    }
+}
+
+pub fn ash_clear(args: Vec<Value>) -> Value {
+    match &args[..] {
+        [Value::ListValue(_l)] => _l.borrow_mut().clear(),
+        [Value::MapValue(_m)] => _m.borrow_mut().clear(),
+        _ => panic!("clear expects a List or a Map"),
+    }
+    Value::None
+}
+
+// extend(xs, ys) appends the elements of ys, extend(m, other) adds the entries of other
+pub fn ash_extend(args: Vec<Value>) -> Value {
+    if let [_target, _other] = &args[..] {
+        // The elements are checked, extending a List with itself is fine
+        match _other {
+            Value::ListValue(_r) => _r.borrow().iter().for_each(|_v| check_cycle(_target, _v)),
+            Value::MapValue(_r) => _r.borrow().values().for_each(|_v| check_cycle(_target, _v)),
+            Value::TupleValue(_r) => _r.iter().for_each(|_v| check_cycle(_target, _v)),
+            _ => {}
+        }
+    }
+    match &args[..] {
+        [Value::ListValue(_l), Value::ListValue(_r)] => {
+            let values = _r.borrow().clone();
+            _l.borrow_mut().extend(values);
+        }
+        [Value::ListValue(_l), Value::TupleValue(_r)] => _l.borrow_mut().extend(_r.clone()),
+        [Value::ListValue(_l), Value::SetValue(_r)] => {
+            _l.borrow_mut().extend(_r.iter().map(Value::deep_copy))
+        }
+        [Value::MapValue(_m), Value::MapValue(_r)] => {
+            let entries = _r.borrow().clone();
+            _m.borrow_mut().extend(entries);
+        }
+        _ => panic!("extend expects a List and a List, Tuple or Set or two Maps"),
+    }
+    Value::None
+}
+
+// copy(x) is a new List or Map holding the same elements
+pub fn ash_copy(args: Vec<Value>) -> Value {
+    match args.first() {
+        Some(Value::ListValue(_l)) if args.len() == 1 => Value::list(_l.borrow().clone()),
+        Some(Value::MapValue(_m)) if args.len() == 1 => Value::map(_m.borrow().clone()),
+        Some(_val) if args.len() == 1 => _val.clone(),
+        _ => panic!("copy expects 1 argument"),
+    }
+}
+
+// deepcopy(x) also copies the Lists and Maps nested in x
+pub fn ash_deepcopy(args: Vec<Value>) -> Value {
+    match args.first() {
+        Some(_val) if args.len() == 1 => _val.deep_copy(),
+        _ => panic!("deepcopy expects 1 argument"),
+    }
+}
+
+fn set_operands(args: &[Value], name: &str) -> (HashSet<Value>, HashSet<Value>) {
+    match args {
+        [Value::SetValue(l), Value::SetValue(r)] => (l.clone(), r.clone()),
+        _ => panic!("{} expects two Sets", name),
+    }
+}
+
+pub fn ash_union(args: Vec<Value>) -> Value {
+    let (l, r) = set_operands(&args, "union");
+    Value::SetValue(&l | &r)
+}
+
+pub fn ash_intersection(args: Vec<Value>) -> Value {
+    let (l, r) = set_operands(&args, "intersection");
+    Value::SetValue(&l & &r)
+}
+
+pub fn ash_difference(args: Vec<Value>) -> Value {
+    let (l, r) = set_operands(&args, "difference");
+    Value::SetValue(&l - &r)
+}
+
+pub fn ash_is_subset(args: Vec<Value>) -> Value {
+    let (l, r) = set_operands(&args, "is_subset");
+    Value::BooleanValue(l.is_subset(&r))
+}
+
+pub fn ash_tuple(args: Vec<Value>) -> Value {
+    match ash_list(args) {
+        Value::ListValue(_l) => Value::TupleValue(_l.take()),
+        _ => unreachable!(),
+    }
+}
+
+// divmod(a, b) returns the floored quotient and the remainder as a Tuple
+pub fn ash_divmod(args: Vec<Value>) -> Value {
+    let to_big_int = |val: &Value| match val {
+        Value::IntValue(_i) => BigInt::from(*_i),
+        Value::BigIntValue(_i) => _i.clone(),
+        _ => panic!("divmod expects Int arguments, found {}", val.type_name()),
+    };
+    let (l, r) = match &args[..] {
+        [l, r] => (to_big_int(l), to_big_int(r)),
+        _ => panic!("Invalid arguments"),
+    };
+    if r.is_zero() {
+        panic!("Division by zero")
     }
+    let (q, m) = l.div_mod_floor(&r);
+    Value::TupleValue(vec![Value::from_big_int(q), Value::from_big_int(m)])
 }
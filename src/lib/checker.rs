@@ -0,0 +1,1002 @@
+use std::collections::HashMap;
+
+use crate::{nodes::*, tokens::PosRange};
+
+// Gradual type checker. Unannotated values are Any and agree with every
+// type, so only mismatches between known types are reported.
+pub struct Checker {
+    errors: Vec<String>,
+    // Variables of each enclosing scope, innermost last, and whether their
+    // type was declared with an annotation
+    scopes: Vec<HashMap<String, (Type, bool)>>,
+    // Functions declared in each enclosing block, innermost last
+    functions: Vec<HashMap<String, Signature>>,
+    builtins: HashMap<String, Signature>,
+    // Methods of builtin types, keyed by "Type.name"
+    methods: HashMap<String, Signature>,
+    structs: HashMap<String, Vec<(String, Type)>>,
+    // Methods of user types, self included
+    user_methods: HashMap<String, HashMap<String, Signature>>,
+    traits: HashMap<String, Vec<(String, Signature, bool)>>,
+    // What `return` and `yield` may produce in the enclosing functions
+    contexts: Vec<(Type, Type)>,
+    // The enclosing @pure function
+    pure: Option<String>,
+    // Source range of the statement being checked
+    span: PosRange,
+}
+
+// Parameters and return type of a function, as the checker sees them
+#[derive(Debug, Clone)]
+struct Signature {
+    params: Vec<(String, Type)>,
+    // Number of trailing params that may be left out
+    optional: usize,
+    // Type of each extra argument, when the function takes any number
+    rest: Option<Type>,
+    ret: Type,
+}
+
+impl Signature {
+    fn new(params: Vec<Type>, ret: Type) -> Self {
+        Signature {
+            params: params.into_iter().map(|_t| (String::new(), _t)).collect(),
+            optional: 0,
+            rest: None,
+            ret,
+        }
+    }
+
+    fn optional(mut self, count: usize) -> Self {
+        self.optional = count;
+        self
+    }
+
+    fn rest(mut self, ty: Type) -> Self {
+        self.rest = Some(ty);
+        self
+    }
+
+    fn declared(params: &[Param], ret: &Option<Type>, generator: bool) -> Self {
+        let mut sig = Signature::new(Vec::new(), Type::Any);
+        for param in params {
+            let ty = param.ty.clone().unwrap_or(Type::Any);
+            if param.rest {
+                sig.rest = Some(ty);
+            } else {
+                if param.default.is_some() {
+                    sig.optional += 1;
+                }
+                sig.params.push((param.id.clone(), ty));
+            }
+        }
+        sig.ret = match ret {
+            Some(_t) => _t.clone(),
+            None if generator => Type::Iterator(Box::new(Type::Any)),
+            None => Type::Any,
+        };
+        sig
+    }
+}
+
+fn list(ty: Type) -> Type {
+    Type::List(Box::new(ty))
+}
+
+fn set(ty: Type) -> Type {
+    Type::Set(Box::new(ty))
+}
+
+fn iterator(ty: Type) -> Type {
+    Type::Iterator(Box::new(ty))
+}
+
+fn builtin_signatures() -> HashMap<String, Signature> {
+    use Type::*;
+    [
+        ("print", Signature::new(vec![], None).rest(Any)),
+        ("println", Signature::new(vec![], None).rest(Any)),
+        ("input", Signature::new(vec![String], String).optional(1)),
+        ("int", Signature::new(vec![Any], Int)),
+        ("double", Signature::new(vec![Any], Double)),
+        ("frac", Signature::new(vec![Any, Any], Rational).optional(1)),
+        ("str", Signature::new(vec![Any], String)),
+        ("list", Signature::new(vec![Any], list(Any))),
+        ("min", Signature::new(vec![Any], Any).rest(Any)),
+        ("max", Signature::new(vec![Any], Any).rest(Any)),
+        ("get", Signature::new(vec![Any, Any], Any)),
+        ("set", Signature::new(vec![Any, Any, Any], Any)),
+        ("len", Signature::new(vec![Any], Int)),
+        ("pop", Signature::new(vec![Any, Any], Any)),
+        (
+            "slice",
+            Signature::new(vec![Any, Int, Int], Any).optional(1),
+        ),
+        (
+            "keys",
+            Signature::new(vec![Map(Box::new(Any), Box::new(Any))], list(Any)),
+        ),
+        ("has", Signature::new(vec![Any, Any], Boolean)),
+        ("trim", Signature::new(vec![String], String)),
+        (
+            "split",
+            Signature::new(vec![String, String], list(String)).optional(1),
+        ),
+        (
+            "join",
+            Signature::new(vec![Any, String], String).optional(1),
+        ),
+        ("union", Signature::new(vec![set(Any), set(Any)], set(Any))),
+        (
+            "intersection",
+            Signature::new(vec![set(Any), set(Any)], set(Any)),
+        ),
+        (
+            "difference",
+            Signature::new(vec![set(Any), set(Any)], set(Any)),
+        ),
+        (
+            "is_subset",
+            Signature::new(vec![set(Any), set(Any)], Boolean),
+        ),
+        ("tuple", Signature::new(vec![Any], Any)),
+        (
+            "divmod",
+            Signature::new(vec![Int, Int], Tuple(vec![Int, Int])),
+        ),
+        ("push", Signature::new(vec![list(Any), Any], None)),
+        ("insert", Signature::new(vec![Any, Any, Any], None)),
+        ("remove", Signature::new(vec![Any, Any], Any)),
+        ("clear", Signature::new(vec![Any], None)),
+        ("extend", Signature::new(vec![Any, Any], None)),
+        ("sort", Signature::new(vec![list(Any)], None)),
+        ("repr", Signature::new(vec![Any], String)),
+        ("pprint", Signature::new(vec![Any, Int], None).optional(1)),
+        ("copy", Signature::new(vec![Any], Any)),
+        ("deepcopy", Signature::new(vec![Any], Any)),
+        ("cache_clear", Signature::new(vec![Any], None)),
+        (
+            "cache_stats",
+            Signature::new(vec![Any], Map(Box::new(String), Box::new(Any))),
+        ),
+        ("iter", Signature::new(vec![Any], iterator(Any))),
+        (
+            "range",
+            Signature::new(vec![Int, Int, Int], iterator(Int)).optional(2),
+        ),
+        (
+            "next",
+            Signature::new(vec![iterator(Any), Any], Any).optional(1),
+        ),
+        ("has_next", Signature::new(vec![iterator(Any)], Boolean)),
+        ("take", Signature::new(vec![Any, Int], iterator(Any))),
+        ("skip", Signature::new(vec![Any, Int], iterator(Any))),
+        ("collect", Signature::new(vec![Any], list(Any))),
+        ("to_set", Signature::new(vec![Any], set(Any))),
+    ]
+    .into_iter()
+    .map(|(_id, _sig)| (_id.to_string(), _sig))
+    .collect()
+}
+
+// Builtins that do I/O or change their arguments, which @pure functions can't call
+const IMPURE_BUILTINS: [&str; 12] = [
+    "print", "println", "pprint", "input", "set", "pop", "push", "insert", "remove", "clear",
+    "extend", "sort",
+];
+
+fn method_signatures() -> HashMap<String, Signature> {
+    use Type::*;
+    [
+        ("String.upper", Signature::new(vec![String], String)),
+        ("String.lower", Signature::new(vec![String], String)),
+        (
+            "String.starts_with",
+            Signature::new(vec![String, String], Boolean),
+        ),
+        (
+            "String.ends_with",
+            Signature::new(vec![String, String], Boolean),
+        ),
+    ]
+    .into_iter()
+    .map(|(_id, _sig)| (_id.to_string(), _sig))
+    .collect()
+}
+
+// Whether a value of type `actual` can be used where `expected` is declared
+fn accepts(expected: &Type, actual: &Type) -> bool {
+    match (expected, actual) {
+        (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Double, Type::Int | Type::Rational) | (Type::Rational, Type::Int) => true,
+        (Type::List(_e), Type::List(_a))
+        | (Type::Set(_e), Type::Set(_a))
+        | (Type::Iterator(_e), Type::Iterator(_a)) => accepts(_e, _a),
+        (Type::Map(_ek, _ev), Type::Map(_ak, _av)) => accepts(_ek, _ak) && accepts(_ev, _av),
+        (Type::Tuple(_es), Type::Tuple(_as)) => {
+            _es.len() == _as.len() && _es.iter().zip(_as).all(|(_e, _a)| accepts(_e, _a))
+        }
+        _ => expected == actual,
+    }
+}
+
+fn is_numeric(ty: &Type) -> bool {
+    matches!(ty, Type::Int | Type::Double | Type::Rational)
+}
+
+// The narrowest type both values fit in
+fn join(left: &Type, right: &Type) -> Type {
+    match (left, right) {
+        _ if left == right => left.clone(),
+        (Type::Double, _) | (_, Type::Double) if is_numeric(left) && is_numeric(right) => {
+            Type::Double
+        }
+        (Type::Rational, _) | (_, Type::Rational) if is_numeric(left) && is_numeric(right) => {
+            Type::Rational
+        }
+        (Type::List(_l), Type::List(_r)) => list(join(_l, _r)),
+        (Type::Set(_l), Type::Set(_r)) => set(join(_l, _r)),
+        (Type::Map(_lk, _lv), Type::Map(_rk, _rv)) => {
+            Type::Map(Box::new(join(_lk, _rk)), Box::new(join(_lv, _rv)))
+        }
+        _ => Type::Any,
+    }
+}
+
+fn join_all(types: impl Iterator<Item = Type>) -> Type {
+    types
+        .reduce(|_acc, _t| join(&_acc, &_t))
+        .unwrap_or(Type::Any)
+}
+
+// Type of the values produced by iterating over a value of type `ty`
+fn element(ty: &Type) -> Type {
+    match ty {
+        Type::List(_t) | Type::Set(_t) | Type::Iterator(_t) => *_t.clone(),
+        Type::Map(_k, _) => *_k.clone(),
+        Type::String => Type::String,
+        _ => Type::Any,
+    }
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            errors: Vec::new(),
+            scopes: vec![HashMap::new()],
+            functions: Vec::new(),
+            builtins: builtin_signatures(),
+            methods: method_signatures(),
+            structs: HashMap::new(),
+            user_methods: HashMap::new(),
+            traits: HashMap::new(),
+            contexts: Vec::new(),
+            pure: None,
+            span: PosRange::empty(),
+        }
+    }
+
+    // Checks a parsed program without running it, returning every type error
+    pub fn check(&mut self, ast: &Node) -> Vec<String> {
+        if let Node::BlockStatement(_program) = ast {
+            self.declare_types(&_program.value);
+        }
+        self.check_statement(ast);
+        std::mem::take(&mut self.errors)
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors
+            .push(format!("Type Error {}: {}", self.span.get_pos(), message));
+    }
+
+    fn expect(&mut self, expected: &Type, actual: &Type, context: &str) {
+        if !accepts(expected, actual) {
+            self.error(format!("{context} expects {expected}, found {actual}"));
+        }
+    }
+
+    // Collects structs, traits and impls up front, as they can be used before
+    // the declaration in the source
+    fn declare_types(&mut self, program: &[Node]) {
+        for node in program {
+            match node {
+                Node::StructDeclaration(_sd) => {
+                    let fields = _sd
+                        .fields
+                        .iter()
+                        .map(|(_id, _ty)| (_id.clone(), _ty.clone().unwrap_or(Type::Any)))
+                        .collect();
+                    self.structs.insert(_sd.id.clone(), fields);
+                }
+                Node::TraitDeclaration(_td) => {
+                    let methods = _td
+                        .methods
+                        .iter()
+                        .filter_map(|_m| match _m {
+                            Node::FunctionSignature(_fns) => Some((
+                                _fns.id.clone(),
+                                Signature::declared(&_fns.params, &_fns.ret, false),
+                                true,
+                            )),
+                            Node::FunctionDeclaration(_fnd) => Some((
+                                _fnd.id.clone(),
+                                Signature::declared(&_fnd.params, &_fnd.ret, _fnd.generator),
+                                false,
+                            )),
+                            _ => None,
+                        })
+                        .collect();
+                    self.traits.insert(_td.id.clone(), methods);
+                }
+                _ => {}
+            }
+        }
+        for node in program {
+            if let Node::Impl(_im) = node {
+                let mut methods = HashMap::new();
+                for method in _im.methods.iter() {
+                    if let Node::FunctionDeclaration(_fnd) = method {
+                        let sig = Signature::declared(&_fnd.params, &_fnd.ret, _fnd.generator);
+                        methods.insert(_fnd.id.clone(), sig);
+                    }
+                }
+                if let Some(trait_id) = &_im.trait_id {
+                    for (id, sig, required) in
+                        self.traits.get(trait_id).cloned().unwrap_or_default()
+                    {
+                        if methods.contains_key(&id) {
+                            continue;
+                        }
+                        if required {
+                            self.error(format!(
+                                "impl {} for {} is missing method '{}'",
+                                trait_id, _im.type_id, id
+                            ));
+                        }
+                        methods.insert(id, sig);
+                    }
+                }
+                self.user_methods
+                    .entry(_im.type_id.clone())
+                    .or_default()
+                    .extend(methods);
+            }
+        }
+    }
+
+    // Reports annotations naming structs that don't exist
+    fn check_type(&mut self, ty: &Type) {
+        match ty {
+            Type::Struct(_id) if !self.structs.contains_key(_id) => {
+                self.error(format!("Unknown type '{_id}'"))
+            }
+            Type::List(_t) | Type::Set(_t) | Type::Iterator(_t) => self.check_type(_t),
+            Type::Map(_k, _v) => {
+                self.check_type(_k);
+                self.check_type(_v);
+            }
+            Type::Tuple(_ts) => _ts.iter().for_each(|_t| self.check_type(_t)),
+            _ => {}
+        }
+    }
+
+    fn declare(&mut self, id: &str, ty: Type, annotated: bool) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(id.to_string(), (ty, annotated));
+    }
+
+    fn lookup(&self, id: &str) -> Type {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|_scope| _scope.get(id))
+            .map(|(_ty, _)| _ty.clone())
+            .unwrap_or(Type::Any)
+    }
+
+    // Annotated variables keep their type, the others widen to fit
+    fn assign(&mut self, id: &str, ty: Type) {
+        let var = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|_scope| _scope.get_mut(id));
+        let message = match var {
+            Some((_declared, true)) if !accepts(_declared, &ty) => {
+                format!("'{id}' is declared as {_declared} but assigned {ty}")
+            }
+            Some((_current, false)) => {
+                *_current = join(_current, &ty);
+                return;
+            }
+            _ => return,
+        };
+        self.error(message);
+    }
+
+    fn check_block(&mut self, block: &BlockStatementNode) {
+        let outer = self.span.clone();
+        self.scopes.push(HashMap::new());
+        let functions = block
+            .value
+            .iter()
+            .filter_map(|_stmt| match _stmt {
+                Node::FunctionDeclaration(_fnd) => Some((
+                    _fnd.id.clone(),
+                    Signature::declared(&_fnd.params, &_fnd.ret, _fnd.generator),
+                )),
+                _ => None,
+            })
+            .collect();
+        self.functions.push(functions);
+
+        for (idx, stmt) in block.value.iter().enumerate() {
+            if let Some(span) = block.spans.get(idx).filter(|_s| **_s != PosRange::empty()) {
+                self.span = span.clone();
+            }
+            self.check_statement(stmt);
+        }
+
+        self.functions.pop();
+        self.scopes.pop();
+        self.span = outer;
+    }
+
+    fn check_function(&mut self, node: &FunctionDeclarationNode, self_type: Option<&str>) {
+        self.scopes.push(HashMap::new());
+        for (idx, param) in node.params.iter().enumerate() {
+            let ty = match (&param.ty, self_type) {
+                (Some(_ty), _) => {
+                    self.check_type(_ty);
+                    _ty.clone()
+                }
+                (None, Some(_type)) if idx == 0 && param.id == "self" => {
+                    Type::Struct(_type.to_string())
+                }
+                _ => Type::Any,
+            };
+            if let Some(default) = &param.default {
+                let default = self.infer(default);
+                self.expect(&ty, &default, &format!("Parameter '{}'", param.id));
+            }
+            let ty = if param.rest { list(ty) } else { ty };
+            self.declare(&param.id, ty, param.ty.is_some());
+        }
+
+        let ret = node.ret.clone().unwrap_or(Type::Any);
+        self.check_type(&ret);
+        self.contexts.push(if node.generator {
+            (Type::Any, element(&ret))
+        } else {
+            (ret, Type::Any)
+        });
+        let pure = match node.annotation("pure") {
+            Some(_) => Some(node.id.clone()),
+            None => self.pure.clone(),
+        };
+        let pure = std::mem::replace(&mut self.pure, pure);
+        self.check_statement(&node.body);
+        self.pure = pure;
+        self.contexts.pop();
+        self.scopes.pop();
+    }
+
+    fn check_statement(&mut self, node: &Node) {
+        match node {
+            Node::BlockStatement(_block) => self.check_block(_block),
+            Node::Declaration(_dec) => {
+                let ty = self.infer(&_dec.value);
+                match &_dec.ty {
+                    Some(_declared) => {
+                        self.check_type(_declared);
+                        self.expect(_declared, &ty, &format!("'{}'", _dec.id));
+                        self.declare(&_dec.id, _declared.clone(), true);
+                    }
+                    None => self.declare(&_dec.id, ty, false),
+                }
+            }
+            Node::MultiDeclaration(_mdec) => _mdec
+                .declarations
+                .iter()
+                .for_each(|_d| self.check_statement(_d)),
+            Node::Destructure(_des) => {
+                self.infer(&_des.value);
+                let mut names = Vec::new();
+                _des.pattern.names(&mut names);
+                for name in names {
+                    if _des.declare {
+                        self.declare(&name, Type::Any, false);
+                    } else {
+                        self.assign(&name, Type::Any);
+                    }
+                }
+            }
+            Node::Assignment(_a) => {
+                let right = self.infer(&_a.value);
+                let op = match _a.assign_type {
+                    Assignment::Equals => None,
+                    Assignment::PlusEq => Some(Arithmetic::Addition),
+                    Assignment::MinusEq => Some(Arithmetic::Subtraction),
+                    Assignment::MultiplyEq => Some(Arithmetic::Multiply),
+                    Assignment::DivideEq => Some(Arithmetic::Divide),
+                    Assignment::ModulusEq => Some(Arithmetic::Modulus),
+                    Assignment::PowerEq => Some(Arithmetic::Power),
+                    Assignment::TildeDivideEq => Some(Arithmetic::TildeDivide),
+                    Assignment::PowerDivideEq => Some(Arithmetic::PowerDivide),
+                    Assignment::BitAndEq => Some(Arithmetic::BitAnd),
+                    Assignment::BitOrEq => Some(Arithmetic::BitOr),
+                    Assignment::BitXorEq => Some(Arithmetic::BitXor),
+                    Assignment::ShiftLeftEq => Some(Arithmetic::ShiftLeft),
+                    Assignment::ShiftRightEq => Some(Arithmetic::ShiftRight),
+                };
+                let ty = match op {
+                    Some(_op) => {
+                        let left = self.lookup(&_a.id);
+                        self.arithmetic(_op, &left, &right)
+                    }
+                    None => right,
+                };
+                self.assign(&_a.id, ty);
+            }
+            Node::FunctionDeclaration(_fnd) => self.check_function(_fnd, None),
+            Node::StructDeclaration(_sd) => _sd
+                .fields
+                .iter()
+                .filter_map(|(_, _ty)| _ty.as_ref())
+                .for_each(|_ty| self.check_type(_ty)),
+            Node::Impl(_im) => {
+                for method in _im.methods.iter() {
+                    if let Node::FunctionDeclaration(_fnd) = method {
+                        self.check_function(_fnd, Some(&_im.type_id));
+                    }
+                }
+            }
+            Node::TraitDeclaration(_) | Node::FunctionSignature(_) => {}
+            Node::WhileLoop(_wl) => {
+                self.condition(&_wl.condition);
+                self.check_statement(&_wl.body);
+            }
+            Node::IfStatement(_if) => {
+                self.condition(&_if.condition);
+                self.check_statement(&_if.true_block);
+                for elif in _if.elif_blocks.iter() {
+                    if let Node::ElifStatement(_elif) = elif {
+                        self.condition(&_elif.condition);
+                        self.check_statement(&_elif.true_block);
+                    }
+                }
+                if let Some(else_block) = &_if.else_block {
+                    self.check_statement(else_block);
+                }
+            }
+            Node::Return(_rtn) => {
+                let ty = match &_rtn.res {
+                    Some(_res) => self.infer(_res),
+                    None => Type::None,
+                };
+                if let Some((ret, _)) = self.contexts.last().cloned() {
+                    if _rtn.res.is_some() || ret != Type::Any {
+                        self.expect(&ret, &ty, "Return");
+                    }
+                }
+            }
+            Node::Yield(_y) => {
+                let ty = self.infer(&_y.value);
+                if let Some((_, yields)) = self.contexts.last().cloned() {
+                    self.expect(&yields, &ty, "Yield");
+                }
+            }
+            Node::Break(_) | Node::Continue(_) | Node::Comment(_) => {}
+            _ => {
+                self.infer(node);
+            }
+        }
+    }
+
+    fn condition(&mut self, node: &Node) {
+        let ty = self.infer(node);
+        self.expect(&Type::Boolean, &ty, "Condition");
+    }
+
+    fn infer(&mut self, node: &Node) -> Type {
+        match node {
+            Node::Int(_) | Node::BigInt(_) => Type::Int,
+            Node::Double(_) => Type::Double,
+            Node::Boolean(_) => Type::Boolean,
+            Node::String(_) => Type::String,
+            Node::Identifier(_id) => self.lookup(&_id.value),
+            Node::List(_l) => list(self.infer_elements(&_l.elements)),
+            Node::Set(_s) => set(self.infer_elements(&_s.elements)),
+            Node::Tuple(_t) => Type::Tuple(_t.elements.iter().map(|_e| self.infer(_e)).collect()),
+            Node::Map(_m) => {
+                let entries = _m
+                    .elements
+                    .iter()
+                    .map(|(_k, _v)| (self.infer(_k), self.infer(_v)))
+                    .collect::<Vec<(Type, Type)>>();
+                let keys = join_all(entries.iter().map(|(_k, _)| _k.clone()));
+                let values = join_all(entries.into_iter().map(|(_, _v)| _v));
+                Type::Map(Box::new(keys), Box::new(values))
+            }
+            Node::ListComprehension(_lc) => {
+                self.scopes.push(HashMap::new());
+                self.clauses(&_lc.clauses);
+                let ty = list(self.infer(&_lc.element));
+                self.scopes.pop();
+                ty
+            }
+            Node::MapComprehension(_mc) => {
+                self.scopes.push(HashMap::new());
+                self.clauses(&_mc.clauses);
+                let key = self.infer(&_mc.key);
+                let value = self.infer(&_mc.value);
+                self.scopes.pop();
+                Type::Map(Box::new(key), Box::new(value))
+            }
+            Node::UnaryNumber(_un) => {
+                let ty = self.infer(&_un.value);
+                match (&ty, _un.op) {
+                    (Type::Any | Type::Struct(_), _) => Type::Any,
+                    (Type::Int, UnaryArithmetic::BitNot) => Type::Int,
+                    (_, UnaryArithmetic::BitNot) => {
+                        self.error(format!("Operator '~' can't be applied to {ty}"));
+                        Type::Any
+                    }
+                    _ if is_numeric(&ty) => ty,
+                    _ => {
+                        self.error(format!("Operator '{}' can't be applied to {ty}", _un.op));
+                        Type::Any
+                    }
+                }
+            }
+            Node::UnaryBoolean(_ub) => {
+                let ty = self.infer(&_ub.value);
+                self.expect(&Type::Boolean, &ty, &format!("Operator '{}'", _ub.op));
+                Type::Boolean
+            }
+            Node::BinaryOpNumber(_bon) => {
+                let left = self.infer(&_bon.left);
+                let right = self.infer(&_bon.right);
+                self.arithmetic(_bon.op, &left, &right)
+            }
+            Node::BinaryOpBoolean(_bob) => {
+                let left = self.infer(&_bob.left);
+                let right = self.infer(&_bob.right);
+                self.comparison(_bob.op, &left, &right)
+            }
+            Node::FunctionCall(_fnc) => self.call(&_fnc.id, &_fnc.args, Vec::new()),
+            Node::Pipe(_p) => {
+                let value = self.infer(&_p.value);
+                self.call(&_p.call.id, &_p.call.args, vec![value])
+            }
+            Node::MethodCall(_mc) => {
+                let value = self.infer(&_mc.value);
+                let id = &_mc.call.id;
+                let sig = match &value {
+                    Type::Struct(_type) => self
+                        .user_methods
+                        .get(_type)
+                        .and_then(|_methods| _methods.get(id)),
+                    _ => self.methods.get(&format!("{value}.{id}")),
+                };
+                match sig.cloned() {
+                    Some(_sig) => self.check_call(id, &_sig, &_mc.call.args, vec![value]),
+                    None => self.call(id, &_mc.call.args, vec![value]),
+                }
+            }
+            Node::FieldAccess(_fa) => {
+                let value = self.infer(&_fa.value);
+                let field = match &value {
+                    Type::Any => Some(Type::Any),
+                    Type::Struct(_type) => self.structs.get(_type).and_then(|_fields| {
+                        _fields
+                            .iter()
+                            .find(|(_id, _)| *_id == _fa.field)
+                            .map(|(_, _ty)| _ty.clone())
+                    }),
+                    _ => None,
+                };
+                field.unwrap_or_else(|| {
+                    self.error(format!("{value} has no field '{}'", _fa.field));
+                    Type::Any
+                })
+            }
+            _ => Type::Any,
+        }
+    }
+
+    fn infer_elements(&mut self, elements: &[Node]) -> Type {
+        let types = elements
+            .iter()
+            .map(|_e| match _e {
+                Node::Spread(_s) => {
+                    let ty = self.infer(&_s.value);
+                    element(&ty)
+                }
+                _ => self.infer(_e),
+            })
+            .collect::<Vec<Type>>();
+        join_all(types.into_iter())
+    }
+
+    fn clauses(&mut self, clauses: &[ComprehensionClause]) {
+        for clause in clauses {
+            match clause {
+                ComprehensionClause::For { targets, iterable } => {
+                    let ty = self.infer(iterable);
+                    if let [Pattern::Identifier(_id)] = &targets[..] {
+                        self.declare(_id, element(&ty), false);
+                    } else {
+                        let mut names = Vec::new();
+                        targets.iter().for_each(|_t| _t.names(&mut names));
+                        names
+                            .iter()
+                            .for_each(|_name| self.declare(_name, Type::Any, false));
+                    }
+                }
+                ComprehensionClause::If(_cond) => self.condition(_cond),
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, op: Arithmetic, left: &Type, right: &Type) -> Type {
+        let int_only = matches!(
+            op,
+            Arithmetic::BitAnd
+                | Arithmetic::BitOr
+                | Arithmetic::BitXor
+                | Arithmetic::ShiftLeft
+                | Arithmetic::ShiftRight
+        );
+        let supported = match left {
+            Type::Any | Type::Struct(_) => true,
+            Type::Int => true,
+            Type::Double | Type::Rational => !int_only,
+            Type::String | Type::List(_) => {
+                matches!(op, Arithmetic::Addition | Arithmetic::Multiply)
+            }
+            Type::Map(_, _) => op == Arithmetic::Addition,
+            Type::Set(_) => matches!(
+                op,
+                Arithmetic::BitOr
+                    | Arithmetic::BitAnd
+                    | Arithmetic::BitXor
+                    | Arithmetic::Subtraction
+            ),
+            _ => false,
+        };
+        if !supported {
+            self.error(format!("Operator '{op}' can't be applied to {left}"));
+            return Type::Any;
+        }
+
+        let res = match (left, right) {
+            (Type::Any | Type::Struct(_), _) | (_, Type::Any) => Some(Type::Any),
+            _ if int_only => (left == &Type::Int && right == &Type::Int).then_some(Type::Int),
+            _ if is_numeric(left) && is_numeric(right) => Some(match op {
+                Arithmetic::TildeDivide | Arithmetic::PowerDivide => Type::Int,
+                Arithmetic::Divide | Arithmetic::Power if join(left, right) == Type::Int => {
+                    Type::Any
+                }
+                _ => join(left, right),
+            }),
+            (Type::String, Type::String) | (Type::List(_), Type::List(_)) => {
+                (op == Arithmetic::Addition).then(|| join(left, right))
+            }
+            (Type::String | Type::List(_), Type::Int) => {
+                (op == Arithmetic::Multiply).then(|| left.clone())
+            }
+            (Type::Map(_, _), Type::Map(_, _)) | (Type::Set(_), Type::Set(_)) => {
+                Some(join(left, right))
+            }
+            _ => None,
+        };
+        res.unwrap_or_else(|| {
+            self.error(format!(
+                "Operator '{op}' can't be applied to {left} and {right}"
+            ));
+            Type::Any
+        })
+    }
+
+    fn comparison(&mut self, op: Comparison, left: &Type, right: &Type) -> Type {
+        let known = !matches!(left, Type::Any | Type::Struct(_)) && *right != Type::Any;
+        match op {
+            Comparison::DoubleEquals | Comparison::NotEquals => Type::Boolean,
+            Comparison::And | Comparison::Or => match (left, right) {
+                _ if !known => Type::Any,
+                (Type::Boolean, Type::Boolean) => Type::Boolean,
+                (Type::Set(_), Type::Set(_)) => join(left, right),
+                (Type::String, Type::String) | (Type::List(_), Type::List(_))
+                    if op == Comparison::Or =>
+                {
+                    join(left, right)
+                }
+                _ => {
+                    self.error(format!(
+                        "Operator '{op}' can't be applied to {left} and {right}"
+                    ));
+                    Type::Any
+                }
+            },
+            _ => {
+                let comparable = match (left, right) {
+                    _ if !known => true,
+                    _ if is_numeric(left) && is_numeric(right) => true,
+                    (Type::String, Type::String)
+                    | (Type::List(_), Type::List(_))
+                    | (Type::Tuple(_), Type::Tuple(_))
+                    | (Type::Set(_), Type::Set(_)) => true,
+                    _ => false,
+                };
+                if !comparable {
+                    self.error(format!("Can't compare {left} and {right} with '{op}'"));
+                }
+                Type::Boolean
+            }
+        }
+    }
+
+    fn call(&mut self, id: &String, args: &[Node], prefix: Vec<Type>) -> Type {
+        let sig = self
+            .functions
+            .iter()
+            .rev()
+            .find_map(|_fns| _fns.get(id))
+            .cloned()
+            .or_else(|| {
+                self.structs.get(id).map(|_fields| Signature {
+                    params: _fields.clone(),
+                    optional: 0,
+                    rest: None,
+                    ret: Type::Struct(id.clone()),
+                })
+            })
+            .or_else(|| self.builtins.get(id).cloned());
+        if let Some(pure) = &self.pure {
+            let shadowed = self.functions.iter().any(|_fns| _fns.contains_key(id));
+            if IMPURE_BUILTINS.contains(&id.as_str()) && !shadowed {
+                let message = format!("@pure function '{pure}' calls '{id}'");
+                self.error(message);
+            }
+        }
+        match sig {
+            Some(_sig) => self.check_call(id, &_sig, args, prefix),
+            None => {
+                self.error(format!("Unknown function '{id}'"));
+                args.iter().for_each(|_a| {
+                    self.infer(_a);
+                });
+                Type::Any
+            }
+        }
+    }
+
+    fn check_call(&mut self, id: &str, sig: &Signature, args: &[Node], prefix: Vec<Type>) -> Type {
+        let mut positional = prefix;
+        let mut named = Vec::new();
+        let mut spread = false;
+        for arg in args {
+            match arg {
+                Node::NamedArgument(_na) => {
+                    let ty = self.infer(&_na.value);
+                    named.push((_na.id.clone(), ty));
+                }
+                Node::Spread(_s) => {
+                    self.infer(&_s.value);
+                    spread = true;
+                }
+                _ => {
+                    let ty = self.infer(arg);
+                    positional.push(ty);
+                }
+            }
+        }
+
+        let given = positional.len() + named.len();
+        let required = sig.params.len() - sig.optional;
+        if !spread && positional.len() > sig.params.len() && sig.rest.is_none() {
+            self.error(format!(
+                "'{id}' takes at most {} arguments but {} were given",
+                sig.params.len(),
+                positional.len()
+            ));
+        } else if !spread && given < required {
+            self.error(format!(
+                "'{id}' takes at least {required} arguments but {given} were given"
+            ));
+        }
+
+        for (idx, ty) in positional.iter().enumerate() {
+            let expected = match sig.params.get(idx) {
+                Some((_, _expected)) => _expected,
+                None => match &sig.rest {
+                    Some(_rest) => _rest,
+                    None => break,
+                },
+            };
+            self.expect(expected, ty, &format!("Argument {} of '{id}'", idx + 1));
+        }
+        for (name, ty) in named {
+            match sig.params.iter().find(|(_param, _)| *_param == name) {
+                Some((_, _expected)) => {
+                    self.expect(_expected, &ty, &format!("Argument '{name}' of '{id}'"))
+                }
+                None => self.error(format!("'{id}' has no parameter '{name}'")),
+            }
+        }
+
+        // Element types the signatures can't express
+        match (id, positional.first()) {
+            ("get", Some(Type::List(_t))) => *_t.clone(),
+            ("get", Some(Type::Map(_, _v))) => *_v.clone(),
+            ("get", Some(Type::String)) => Type::String,
+            ("iter", Some(_ty)) => iterator(element(_ty)),
+            ("copy" | "deepcopy", Some(_ty)) => _ty.clone(),
+            ("collect", Some(_ty)) => list(element(_ty)),
+            ("keys", Some(Type::Map(_k, _))) => list(*_k.clone()),
+            _ => sig.ret.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Lexer, parser::Parser};
+
+    use super::Checker;
+
+    fn check(code: &str) -> Vec<String> {
+        let tokens = Lexer::new(code.to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        Checker::new().check(&ast)
+    }
+
+    #[test]
+    fn annotated_code_checks() {
+        let errors = check(
+            "struct Point { x: Int, y: Int }
+            fn scale(p: Point, by: Double = 2) -> List<Double> { return [p.x * by, p.y * by]; }
+            fn main() { let xs: List<Double> = scale(Point(1, 2)); let n = len(xs) + 1; println(n); }",
+        );
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn type_errors() {
+        let errors = check(
+            r#"fn half(n: Int) -> Int { return n / 2.0; }
+            fn main() { let s: String = 1; let x = "a" - 1; half("2"); }"#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "Type Error [1:26]:[1:40]: Return expects Int, found Double",
+                "Type Error [2:25]:[2:42]: 's' expects String, found Int",
+                "Type Error [2:44]:[2:59]: Operator '-' can't be applied to String",
+                "Type Error [2:61]:[2:70]: Argument 1 of 'half' expects Int, found String",
+            ]
+        );
+    }
+
+    #[test]
+    fn pure_functions() {
+        let errors = check(
+            "@pure fn total(xs) { push(xs, 0); return len(xs); }
+            fn main() { println(total([])); }",
+        );
+        assert_eq!(
+            errors,
+            vec!["Type Error [1:22]:[1:33]: @pure function 'total' calls 'push'"]
+        );
+    }
+}
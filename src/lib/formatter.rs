@@ -1,4 +1,4 @@
-use super::nodes::Node;
+use super::nodes::{returns, BlockStatementNode, LoopKind, Node};
 
 pub struct Formatter {
     indent: u8,
@@ -23,20 +23,16 @@ impl Formatter {
     }
 
     pub fn format(&mut self, node: Node) -> String {
-        self._format(node)
-            .trim()
-            .trim_end_matches("main();")
-            .trim()
-            .to_string()
-            + "\n"
+        self._format(node).trim().to_string() + "\n"
     }
 
     pub fn _format(&mut self, node: Node) -> String {
         match node {
-            Node::Int(_i) => format!("{}", _i.value),
-            Node::Double(_d) => format!("{}", _d.value),
+            Node::Int(_i) => _i.raw,
+            Node::BigInt(_i) => _i.raw,
+            Node::Double(_d) => _d.raw,
             Node::Boolean(_b) => format!("{}", _b.value),
-            Node::String(_s) => format!("\"{}\"", _s.value),
+            Node::String(_s) => _s.raw,
             Node::Comment(_s) => format!("// {}", _s.value.trim_start()),
             Node::Identifier(_id) => _id.value,
             Node::UnaryNumber(_un) => format!("{}{}", _un.op, _un.value),
@@ -44,17 +40,21 @@ impl Formatter {
             Node::BinaryOpNumber(_bon) => format!("{} {} {}", _bon.left, _bon.op, _bon.right),
             Node::BinaryOpBoolean(_bob) => format!("{} {} {}", _bob.left, _bob.op, _bob.right),
             Node::Assignment(_a) => format!("{} {} {};", _a.id, _a.assign_type, _a.value),
-            Node::Declaration(_dec) => format!("let {} = {};", _dec.id, _dec.value),
+            Node::Declaration(_dec) => format!("{}", Node::Declaration(_dec)),
+            Node::Destructure(_des) => format!("{}", Node::Destructure(_des)),
             Node::MultiDeclaration(_mdec) => {
                 "let ".to_string()
                     + _mdec
                         .declarations
                         .iter()
-                        .map(|_d| {
-                            if let Node::Declaration(_dec) = _d {
-                                return format!("{} = {}", _dec.id, _dec.value);
+                        .map(|_d| match _d {
+                            Node::Declaration(_dec) => {
+                                format!("{} = {}", _dec.target(), _dec.value)
                             }
-                            String::new()
+                            Node::Destructure(_des) => {
+                                format!("{} = {}", _des.pattern.declaration_target(), _des.value)
+                            }
+                            _ => String::new(),
                         })
                         .collect::<Vec<String>>()
                         .join(", ")
@@ -87,22 +87,49 @@ impl Formatter {
                 out
             }
             Node::FunctionDeclaration(_fnd) => {
-                format!(
-                    "fn {}({}) {}",
+                // A bare @memo is written as cfn
+                let mut keyword = "fn";
+                let mut out = String::new();
+                for annotation in _fnd.annotations.iter() {
+                    if annotation.id == "memo" && annotation.args.is_empty() {
+                        keyword = "cfn";
+                    } else {
+                        out += &format!("{}\n{}", annotation, self.space());
+                    }
+                }
+                out + &format!(
+                    "{} {}({}){} {}",
+                    keyword,
                     _fnd.id,
-                    _fnd.params.join(", "),
+                    _fnd.params
+                        .iter()
+                        .map(|_p| format!("{}", _p))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    returns(&_fnd.ret),
                     self._format(*_fnd.body)
                 )
             }
-            Node::FunctionCall(_fnc) => {
-                format!("{}(", _fnc.id);
-                _fnc.args
-                    .iter()
-                    .map(|_a| format!("{}", _a))
-                    .collect::<Vec<String>>()
-                    .join(", ");
-                ")".to_string()
-            }
+            Node::FunctionSignature(_fns) => format!("{}", Node::FunctionSignature(_fns)),
+            Node::StructDeclaration(_sd) => format!("{}", Node::StructDeclaration(_sd)),
+            Node::TraitDeclaration(_td) => format!(
+                "trait {} {}",
+                _td.id,
+                self._format(BlockStatementNode::new(_td.methods))
+            ),
+            Node::Impl(_im) => format!(
+                "{} {}",
+                _im.header(),
+                self._format(BlockStatementNode::new(_im.methods))
+            ),
+            Node::FieldAccess(_fa) => format!("{}", Node::FieldAccess(_fa)),
+            Node::FunctionCall(_fnc) => format!("{}", Node::FunctionCall(_fnc)),
+            Node::Pipe(_p) => format!("{}", Node::Pipe(_p)),
+            Node::MethodCall(_mc) => format!("{}", Node::MethodCall(_mc)),
+            Node::ListComprehension(_lc) => format!("{}", Node::ListComprehension(_lc)),
+            Node::MapComprehension(_mc) => format!("{}", Node::MapComprehension(_mc)),
+            Node::NamedArgument(_na) => format!("{}", Node::NamedArgument(_na)),
+            Node::Spread(_s) => format!("{}", Node::Spread(_s)),
 
             Node::Return(_rtn) => {
                 if let Some(res) = &_rtn.res {
@@ -111,27 +138,26 @@ impl Formatter {
                     "return;".to_string()
                 }
             }
-            Node::Break => "break;".to_string(),
-            Node::Continue => "continue;".to_string(),
-            Node::List(_l) => {
-                "[".to_string();
-                _l.elements
-                    .iter()
-                    .map(|_e| format!("{}", _e))
-                    .collect::<Vec<String>>()
-                    .join(", ");
-                "]".to_string()
-            }
-            Node::Map(_m) => {
-                "{".to_string();
-                _m.elements
-                    .iter()
-                    .map(|_e| format!("{}:{}", _e.0, _e.1))
-                    .collect::<Vec<String>>()
-                    .join(", ");
-                "}".to_string()
+            Node::Yield(_y) => format!("{}", Node::Yield(_y)),
+            Node::Break(_b) => format!("{}", Node::Break(_b)),
+            Node::Continue(_c) => format!("{}", Node::Continue(_c)),
+            Node::List(_l) => format!("{}", Node::List(_l)),
+            Node::Map(_m) => format!("{}", Node::Map(_m)),
+            Node::Set(_s) => format!("{}", Node::Set(_s)),
+            Node::Tuple(_t) => format!("{}", Node::Tuple(_t)),
+            Node::WhileLoop(_w) => {
+                let header = _w.header();
+                if _w.kind == LoopKind::DoWhile {
+                    format!(
+                        "{} {} while ({});",
+                        header,
+                        self._format(*_w.body),
+                        _w.condition
+                    )
+                } else {
+                    format!("{} {}", header, self._format(*_w.body))
+                }
             }
-            Node::WhileLoop(_w) => format!("while ({}) {}", _w.condition, self._format(*_w.body)),
             Node::IfStatement(_if) => {
                 format!("if ({}) {}", _if.condition, self._format(*_if.true_block))
                     + _if
@@ -141,8 +167,8 @@ impl Formatter {
                         .collect::<Vec<String>>()
                         .join("")
                         .as_str()
-                    + if _if.else_block.is_some() {
-                        format!(" else {}", self._format(*_if.else_block.unwrap()))
+                    + if let Some(else_block) = _if.else_block {
+                        format!(" else {}", self._format(*else_block))
                     } else {
                         String::new()
                     }
@@ -156,3 +182,34 @@ impl Formatter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Lexer, nodes::Node, parser::Parser};
+
+    use super::Formatter;
+
+    // The first statement of the code
+    fn statement(code: &str) -> Node {
+        let tokens = Lexer::new(code.to_string()).tokenize().unwrap();
+        match Parser::new(tokens).parse().unwrap() {
+            Node::BlockStatement(_program) => _program.value[0].clone(),
+            _ => panic!("Expected BlockStatement"),
+        }
+    }
+
+    #[test]
+    fn calls_lists_and_maps() {
+        let map = match statement("let m = {1: [2], 3: f(4)};") {
+            Node::MultiDeclaration(_mdec) => match &_mdec.declarations[0] {
+                Node::Declaration(_dec) => *_dec.value.clone(),
+                _ => panic!("Expected Declaration"),
+            },
+            _ => panic!("Expected MultiDeclaration"),
+        };
+        let mut formatter = Formatter::new(4);
+        assert_eq!(formatter.format(statement("f(1, [2]);")), "f(1, [2])\n");
+        assert_eq!(formatter.format(statement("[1, g(2)];")), "[1, g(2)]\n");
+        assert_eq!(formatter.format(map), "{1:[2], 3:f(4)}\n");
+    }
+}
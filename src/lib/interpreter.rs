@@ -1,19 +1,71 @@
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
 use ordered_float::OrderedFloat;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use super::built_in::*;
+use super::iterator::*;
+use super::types::*;
 
 use super::nodes::*;
 use super::scope::{Scope, ScopePtr};
+use super::tokens::PosRange;
 use super::values::*;
 
 type BuiltInFn = fn(Vec<Value>) -> Value;
+// Builtins that consume iterators and may have to resume generators
+type IterBuiltInFn = fn(&mut Interpreter, Vec<Value>) -> Value;
+
+// Builtins run by the interpreter itself, as they need Display or the
+// name of a function rather than its value
+const INTERPRETER_BUILTINS: [&str; 7] = [
+    "repr",
+    "pprint",
+    "cache_clear",
+    "cache_stats",
+    "sort",
+    "min",
+    "max",
+];
 
 pub struct Interpreter {
     ast: Node,
     builtin: Rc<HashMap<String, BuiltInFn>>,
+    iter_builtin: Rc<HashMap<String, IterBuiltInFn>>,
+    // Type specific methods, keyed by "Type.name"
+    methods: Rc<HashMap<String, BuiltInFn>>,
+    // Declared structs and traits, by name
+    types: HashMap<String, UserType>,
+    traits: HashMap<String, Trait>,
+    tail_call: Option<TailCall>,
+    // Command line arguments passed to main
+    args: Vec<String>,
+    // Nesting of the @trace calls being run
+    trace_depth: usize,
+    // @deprecated functions already warned about, with the statement calling them
+    deprecated: HashSet<(String, PosRange)>,
+    // Source range of the statement being run
+    span: PosRange,
+    // Whether the last finished call ended in an explicit return
+    returned: bool,
+}
+
+// A `return f(...)` whose arguments are evaluated but whose body hasn't run.
+// Like any call, the callee's scope hangs off the caller's scope, which is
+// flattened so long tail call chains don't keep every caller's scope alive.
+struct TailCall {
+    func: Rc<RefCell<FunctionDeclarationNode>>,
+    vals: Vec<Value>,
+    named: Vec<(String, Value)>,
+    scope: ScopePtr,
+    // The statement making the call
+    span: PosRange,
 }
 
 impl Interpreter {
@@ -24,36 +76,126 @@ impl Interpreter {
             (String::from("input"), ash_input as BuiltInFn),
             (String::from("int"), ash_int as BuiltInFn),
             (String::from("double"), ash_double as BuiltInFn),
+            (String::from("frac"), ash_frac as BuiltInFn),
             (String::from("str"), ash_str as BuiltInFn),
             (String::from("list"), ash_list as BuiltInFn),
-            (String::from("min"), ash_min as BuiltInFn),
-            (String::from("max"), ash_max as BuiltInFn),
             (String::from("get"), ash_get as BuiltInFn),
             (String::from("set"), ash_set as BuiltInFn),
             (String::from("len"), ash_len as BuiltInFn),
             (String::from("pop"), ash_pop as BuiltInFn),
+            (String::from("slice"), ash_slice as BuiltInFn),
             (String::from("keys"), ash_keys as BuiltInFn),
             (String::from("has"), ash_has as BuiltInFn),
+            (String::from("trim"), ash_trim as BuiltInFn),
+            (String::from("split"), ash_split as BuiltInFn),
+            (String::from("join"), ash_join as BuiltInFn),
+            (String::from("union"), ash_union as BuiltInFn),
+            (String::from("intersection"), ash_intersection as BuiltInFn),
+            (String::from("difference"), ash_difference as BuiltInFn),
+            (String::from("is_subset"), ash_is_subset as BuiltInFn),
+            (String::from("tuple"), ash_tuple as BuiltInFn),
+            (String::from("divmod"), ash_divmod as BuiltInFn),
+            (String::from("push"), ash_push as BuiltInFn),
+            (String::from("insert"), ash_insert as BuiltInFn),
+            (String::from("remove"), ash_remove as BuiltInFn),
+            (String::from("clear"), ash_clear as BuiltInFn),
+            (String::from("extend"), ash_extend as BuiltInFn),
+            (String::from("copy"), ash_copy as BuiltInFn),
+            (String::from("deepcopy"), ash_deepcopy as BuiltInFn),
+        ]);
+        let iter_builtin: HashMap<String, IterBuiltInFn> = HashMap::from([
+            (String::from("iter"), ash_iter as IterBuiltInFn),
+            (String::from("range"), ash_range as IterBuiltInFn),
+            (String::from("next"), ash_next as IterBuiltInFn),
+            (String::from("has_next"), ash_has_next as IterBuiltInFn),
+            (String::from("take"), ash_take as IterBuiltInFn),
+            (String::from("skip"), ash_skip as IterBuiltInFn),
+            (String::from("collect"), ash_collect as IterBuiltInFn),
+            (String::from("to_set"), ash_to_set as IterBuiltInFn),
+        ]);
+        let methods: HashMap<String, BuiltInFn> = HashMap::from([
+            (String::from("String.upper"), ash_upper as BuiltInFn),
+            (String::from("String.lower"), ash_lower as BuiltInFn),
+            (
+                String::from("String.starts_with"),
+                ash_starts_with as BuiltInFn,
+            ),
+            (String::from("String.ends_with"), ash_ends_with as BuiltInFn),
         ]);
         Interpreter {
             ast,
             builtin: Rc::new(builtin),
+            iter_builtin: Rc::new(iter_builtin),
+            methods: Rc::new(methods),
+            types: HashMap::new(),
+            traits: builtin_traits(),
+            tail_call: None,
+            args: Vec::new(),
+            trace_depth: 0,
+            deprecated: HashSet::new(),
+            span: PosRange::empty(),
+            returned: false,
         }
     }
 
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    // Runs the top level statements, then main if it is declared.
+    // Returns what main returns, main(args) gets the command line arguments
     pub fn eval(&mut self) -> Value {
         let mut global_scope = Scope::from(HashMap::new(), HashMap::new());
-        self.walk(&mut self.ast.to_owned(), &mut global_scope)
+        match &mut self.ast.to_owned() {
+            Node::BlockStatement(_program) => {
+                self.walk_block_statement_node(_program, &mut global_scope, false)
+            }
+            _node => self.walk(_node, &mut global_scope),
+        };
+
+        let main = String::from("main");
+        self.returned = false;
+        if !global_scope.borrow().has_function(&main) {
+            return Value::None;
+        }
+        let main = global_scope.borrow().get_function(&main);
+        let args = if main.borrow().params.is_empty() {
+            vec![]
+        } else {
+            let args = self.args.iter().cloned().map(Value::StringValue);
+            vec![Value::list(args.collect())]
+        };
+        self.call_function(main, args, vec![], &mut global_scope)
+    }
+
+    // Runs the program like eval and returns its exit code. Only an Int
+    // explicitly returned by main sets it, anything outside 0..=255 exits with 1
+    pub fn run(&mut self) -> i32 {
+        let res = self.eval();
+        if !self.returned {
+            return 0;
+        }
+        match res {
+            Value::IntValue(_code) if (0..=255).contains(&_code) => _code as i32,
+            Value::IntValue(_) | Value::BigIntValue(_) => 1,
+            _ => 0,
+        }
     }
 
     fn walk(&mut self, node: &mut Node, scope: &mut ScopePtr) -> Value {
         match node {
             Node::Int(_node) => self.walk_int_node(_node),
+            Node::BigInt(_node) => Value::BigIntValue(_node.value.clone()),
             Node::Double(_node) => self.walk_double_node(_node),
             Node::Boolean(_node) => self.walk_boolean_node(_node),
             Node::String(_node) => self.walk_string_node(_node),
             Node::List(_node) => self.walk_list_node(_node, scope),
             Node::Map(_node) => self.walk_map_node(_node, scope),
+            Node::Set(_node) => self.walk_set_node(_node, scope),
+            Node::Tuple(_node) => self.walk_tuple_node(_node, scope),
+            Node::ListComprehension(_node) => self.walk_list_comprehension_node(_node, scope),
+            Node::MapComprehension(_node) => self.walk_map_comprehension_node(_node, scope),
             Node::BinaryOpNumber(_node) => self.walk_binary_op_number_node(_node, scope),
             Node::BinaryOpBoolean(_node) => self.walk_binary_op_boolean_node(_node, scope),
             Node::UnaryNumber(_node) => self.walk_unary_number_node(_node, scope),
@@ -62,14 +204,27 @@ impl Interpreter {
             Node::Identifier(_node) => self.walk_identifier_node(_node, scope),
             Node::BlockStatement(_node) => self.walk_block_statement_node(_node, scope, true),
             Node::FunctionCall(_node) => self.walk_function_call_node(_node, scope),
+            Node::Pipe(_node) => self.walk_pipe_node(_node, scope),
+            Node::MethodCall(_node) => self.walk_method_call_node(_node, scope),
+            Node::NamedArgument(_) => panic!("Named arguments are only allowed in function calls"),
+            Node::Spread(_) => panic!("Spread is only allowed in function calls and lists"),
             Node::FunctionDeclaration(_node) => self.walk_function_declaration_node(_node, scope),
+            Node::StructDeclaration(_node) => self.walk_struct_declaration_node(_node),
+            Node::TraitDeclaration(_node) => self.walk_trait_declaration_node(_node),
+            Node::Impl(_node) => self.walk_impl_node(_node),
+            Node::FieldAccess(_node) => self.walk_field_access_node(_node, scope),
+            Node::FunctionSignature(_) => {
+                panic!("Methods without a body are only allowed in traits")
+            }
             Node::MultiDeclaration(_node) => self.walk_multi_declaration_node(_node, scope),
             Node::Declaration(_node) => self.walk_declaration_node(_node, scope),
+            Node::Destructure(_node) => self.walk_destructure_node(_node, scope),
             Node::WhileLoop(_node) => self.walk_while_loop_node(_node, scope),
             Node::IfStatement(_node) => self.walk_if_statement_node(_node, scope),
             Node::Return(_node) => self.walk_return_node(_node, scope),
-            Node::Break => Value::Break,
-            Node::Continue => Value::Continue,
+            Node::Yield(_) => panic!("yield can only be used inside a generator function"),
+            Node::Break(_label) => Value::Break(_label.clone()),
+            Node::Continue(_label) => Value::Continue(_label.clone()),
             Node::ElifStatement(_) => panic!("This can't happen"),
             Node::Comment(_) => Value::None,
         }
@@ -92,29 +247,153 @@ impl Interpreter {
     }
 
     fn walk_list_node(&mut self, node: &ListNode, scope: &mut ScopePtr) -> Value {
-        Value::ListValue(
-            node.elements
-                .clone()
-                .iter_mut()
-                .map(|e| self.walk(e, scope))
-                .collect(),
-        )
+        let mut res = vec![];
+        for e in node.elements.clone().iter_mut() {
+            match e {
+                Node::Spread(_node) => res.extend(self.walk_spread_node(_node, scope)),
+                _ => res.push(self.walk(e, scope)),
+            }
+        }
+        Value::list(res)
     }
 
     fn walk_map_node(&mut self, node: &MapNode, scope: &mut ScopePtr) -> Value {
-        Value::MapValue(
+        Value::map(
             node.elements
                 .clone()
                 .iter_mut()
-                .map(|(k, v)| (self.walk(&mut k.clone(), scope), self.walk(v, scope)))
+                .map(|(k, v)| {
+                    let key = self.walk(&mut k.clone(), scope).deep_copy();
+                    (key, self.walk(v, scope))
+                })
                 .collect(),
         )
     }
 
+    fn walk_tuple_node(&mut self, node: &mut TupleNode, scope: &mut ScopePtr) -> Value {
+        let mut res = vec![];
+        for e in node.elements.iter_mut() {
+            match e {
+                Node::Spread(_node) => res.extend(self.walk_spread_node(_node, scope)),
+                _ => res.push(self.walk(e, scope)),
+            }
+        }
+        Value::TupleValue(res)
+    }
+
+    fn walk_set_node(&mut self, node: &mut SetNode, scope: &mut ScopePtr) -> Value {
+        let mut res = HashSet::new();
+        for e in node.elements.iter_mut() {
+            match e {
+                Node::Spread(_node) => res.extend(
+                    self.walk_spread_node(_node, scope)
+                        .iter()
+                        .map(Value::deep_copy),
+                ),
+                _ => {
+                    res.insert(self.walk(e, scope).deep_copy());
+                }
+            }
+        }
+        Value::SetValue(res)
+    }
+
+    fn walk_list_comprehension_node(
+        &mut self,
+        node: &mut ListComprehensionNode,
+        scope: &mut ScopePtr,
+    ) -> Value {
+        let element = &mut node.element;
+        let mut res = vec![];
+        self.comprehend(
+            &mut node.clauses,
+            &mut Scope::new(scope.clone()),
+            &mut |this, local| res.push(this.walk(element, local)),
+        );
+        Value::list(res)
+    }
+
+    fn walk_map_comprehension_node(
+        &mut self,
+        node: &mut MapComprehensionNode,
+        scope: &mut ScopePtr,
+    ) -> Value {
+        let (key, value) = (&mut node.key, &mut node.value);
+        let mut res = IndexMap::new();
+        self.comprehend(
+            &mut node.clauses,
+            &mut Scope::new(scope.clone()),
+            &mut |this, local| {
+                let key = this.walk(key, local).deep_copy();
+                res.insert(key, this.walk(value, local));
+            },
+        );
+        Value::map(res)
+    }
+
+    // Runs emit once for every combination of the clauses' items. Loop
+    // variables live in the comprehension's own scope.
+    fn comprehend<F: FnMut(&mut Self, &mut ScopePtr)>(
+        &mut self,
+        clauses: &mut [ComprehensionClause],
+        scope: &mut ScopePtr,
+        emit: &mut F,
+    ) {
+        let (clause, rest) = match clauses.split_first_mut() {
+            Some(_split) => _split,
+            None => return emit(self, scope),
+        };
+        match clause {
+            ComprehensionClause::If(cond) => match self.walk(cond, scope) {
+                Value::BooleanValue(true) => self.comprehend(rest, scope, emit),
+                Value::BooleanValue(false) => {}
+                _ => panic!("Invalid Type in Comprehension Condition"),
+            },
+            ComprehensionClause::For { targets, iterable } => {
+                let pattern = match targets.as_slice() {
+                    [target] => target.to_owned(),
+                    _ => Pattern::Tuple(targets.to_owned()),
+                };
+                let it = match self.walk(iterable, scope) {
+                    // Several targets take a Map's entries as (key, value)
+                    Value::MapValue(_m) if targets.len() > 1 => Iter::new(Source::Values(
+                        _m.borrow()
+                            .iter()
+                            .map(|(k, v)| Value::TupleValue(vec![k.deep_copy(), v.clone()]))
+                            .collect::<Vec<Value>>()
+                            .into_iter(),
+                    )),
+                    _v => to_iterator(_v),
+                };
+                while let Some(item) = self.iter_next(&it) {
+                    self.destructure(&pattern, item, scope, true);
+                    self.comprehend(rest, scope, emit);
+                }
+            }
+        }
+    }
+
     fn walk_return_node(&mut self, node: &mut ReturnNode, scope: &mut ScopePtr) -> Value {
         let mut res = Value::None;
         if let Some(val) = &mut (node.res) {
-            res = self.walk(val, scope);
+            match val.as_mut() {
+                // Tail call, left for the enclosing call_function to run
+                Node::FunctionCall(_call)
+                    if !self.is_builtin(&_call.id, scope)
+                        && !self.is_constructor(&_call.id, scope) =>
+                {
+                    let (vals, named) = self.walk_arguments(&mut _call.args, scope);
+                    let func = scope.borrow().get_function(&_call.id);
+                    self.tail_call = Some(TailCall {
+                        func,
+                        vals,
+                        named,
+                        scope: scope.clone(),
+                        span: self.span.clone(),
+                    });
+                }
+                _ => res = self.walk(val, scope),
+            }
         }
         Value::ReturnValue(Box::new(res))
     }
@@ -130,25 +409,29 @@ impl Interpreter {
         scope: &mut ScopePtr,
         create_scope: bool,
     ) -> Value {
-        let mut res = Value::None;
-        if create_scope {
+        let outer = self.span.clone();
+        let res = if create_scope {
             let local = &mut Scope::new(scope.clone());
-            for stmt in node.value.iter_mut() {
-                res = self.walk(stmt, local);
-                if let Value::ReturnValue(_) = res {
-                    return res;
-                } else if res == Value::Break || res == Value::Continue {
-                    return res;
-                }
-            }
+            self.walk_statements(node, local)
         } else {
-            for stmt in node.value.iter_mut() {
-                res = self.walk(stmt, scope);
-                if let Value::ReturnValue(_) = res {
-                    return res;
-                } else if res == Value::Break || res == Value::Continue {
-                    return res;
-                }
+            self.walk_statements(node, scope)
+        };
+        self.span = outer;
+        res
+    }
+
+    fn walk_statements(&mut self, node: &mut BlockStatementNode, scope: &mut ScopePtr) -> Value {
+        let mut res = Value::None;
+        for (idx, stmt) in node.value.iter_mut().enumerate() {
+            if let Some(span) = node.spans.get(idx).filter(|_s| **_s != PosRange::empty()) {
+                self.span = span.clone();
+            }
+            res = self.walk(stmt, scope);
+            if matches!(
+                res,
+                Value::ReturnValue(_) | Value::Break(_) | Value::Continue(_)
+            ) {
+                return res;
             }
         }
         res
@@ -160,14 +443,41 @@ impl Interpreter {
         scope: &mut ScopePtr,
     ) -> Value {
         let res = self.walk(&mut node.value, scope);
+        if let Value::InstanceValue(_i) = &res {
+            let type_name = _i.type_name.clone();
+            return match self.trait_method(&res, "Neg", "neg") {
+                Some(_fn) if node.op == UnaryArithmetic::Minus => {
+                    self.call_function(_fn, vec![res], vec![], scope)
+                }
+                _ => panic!(
+                    "Invalid Unary Operand! {type_name} does not implement '{}'",
+                    node.op
+                ),
+            };
+        }
         match res {
             Value::IntValue(i) => match node.op {
                 UnaryArithmetic::Plus => Value::IntValue(i),
-                UnaryArithmetic::Minus => Value::IntValue(-i),
+                UnaryArithmetic::Minus => match i.checked_neg() {
+                    Some(i) => Value::IntValue(i),
+                    None => Value::BigIntValue(-BigInt::from(i)),
+                },
+                UnaryArithmetic::BitNot => Value::IntValue(!i),
+            },
+            Value::RationalValue(r) => match node.op {
+                UnaryArithmetic::Plus => Value::RationalValue(r),
+                UnaryArithmetic::Minus => Value::RationalValue(-r),
+                UnaryArithmetic::BitNot => panic!("Invalid Unary Operand! '~' expects an Int"),
+            },
+            Value::BigIntValue(i) => match node.op {
+                UnaryArithmetic::Plus => Value::BigIntValue(i),
+                UnaryArithmetic::Minus => Value::from_big_int(-i),
+                UnaryArithmetic::BitNot => Value::from_big_int(!i),
             },
             Value::DoubleValue(d) => match node.op {
                 UnaryArithmetic::Plus => Value::DoubleValue(d),
                 UnaryArithmetic::Minus => Value::DoubleValue(-d),
+                UnaryArithmetic::BitNot => panic!("Invalid Unary Operand! '~' expects an Int"),
             },
             _ => panic!("Invalid Unary Operand!"),
         }
@@ -195,13 +505,36 @@ impl Interpreter {
         let left = self.walk(&mut node.left, scope);
         let right = self.walk(&mut node.right, scope);
 
+        if let Value::InstanceValue(_) = left {
+            if let Some(res) = self.compare_instance(left.clone(), right.clone(), node.op, scope) {
+                return res;
+            }
+        }
+
+        // Ordering of Sets is inclusion
+        if let (Value::SetValue(l), Value::SetValue(r)) = (&left, &right) {
+            match node.op {
+                Comparison::LessThan => return Value::BooleanValue(l.is_subset(r) && l != r),
+                Comparison::LessThanEq => return Value::BooleanValue(l.is_subset(r)),
+                Comparison::GreaterThan => return Value::BooleanValue(l.is_superset(r) && l != r),
+                Comparison::GreaterThanEq => return Value::BooleanValue(l.is_superset(r)),
+                _ => {}
+            }
+        }
+
         match node.op {
-            Comparison::DoubleEquals => Value::BooleanValue(left == right),
-            Comparison::NotEquals => Value::BooleanValue(left != right),
-            Comparison::LessThan => Value::BooleanValue(left < right),
-            Comparison::LessThanEq => Value::BooleanValue(left <= right),
-            Comparison::GreaterThan => Value::BooleanValue(left > right),
-            Comparison::GreaterThanEq => Value::BooleanValue(left >= right),
+            Comparison::DoubleEquals => Value::BooleanValue(self.equals(&left, &right, scope)),
+            Comparison::NotEquals => Value::BooleanValue(!self.equals(&left, &right, scope)),
+            Comparison::LessThan => Value::BooleanValue(self.compare(&left, &right, scope).is_lt()),
+            Comparison::LessThanEq => {
+                Value::BooleanValue(self.compare(&left, &right, scope).is_le())
+            }
+            Comparison::GreaterThan => {
+                Value::BooleanValue(self.compare(&left, &right, scope).is_gt())
+            }
+            Comparison::GreaterThanEq => {
+                Value::BooleanValue(self.compare(&left, &right, scope).is_ge())
+            }
             Comparison::And => self.and(left, right),
             Comparison::Or => self.or(left, right),
         }
@@ -214,7 +547,285 @@ impl Interpreter {
     ) -> Value {
         let left = self.walk(&mut node.left, scope);
         let right = self.walk(&mut node.right, scope);
-        self.perform_op(left, right, node.op)
+        self.binary_op(left, right, node.op, scope)
+    }
+
+    // Instances go through the operator traits they implement
+    fn binary_op(
+        &mut self,
+        left: Value,
+        right: Value,
+        op: Arithmetic,
+        scope: &mut ScopePtr,
+    ) -> Value {
+        if let Value::InstanceValue(_i) = &left {
+            let type_name = _i.type_name.clone();
+            let method = match op {
+                Arithmetic::Addition => self.trait_method(&left, "Add", "add"),
+                Arithmetic::Subtraction => self.trait_method(&left, "Sub", "sub"),
+                Arithmetic::Multiply => self.trait_method(&left, "Mul", "mul"),
+                Arithmetic::Divide => self.trait_method(&left, "Div", "div"),
+                Arithmetic::Modulus => self.trait_method(&left, "Mod", "mod"),
+                _ => None,
+            };
+            return match method {
+                Some(_fn) => self.call_function(_fn, vec![left, right], vec![], scope),
+                None => panic!("Invalid Operands: {type_name} does not implement '{op}'"),
+            };
+        }
+        self.perform_op(left, right, op)
+    }
+
+    // ==, != through Eq and ordering through Ord; None falls back to
+    // structural equality
+    fn compare_instance(
+        &mut self,
+        left: Value,
+        right: Value,
+        op: Comparison,
+        scope: &mut ScopePtr,
+    ) -> Option<Value> {
+        let type_name = left.type_name().to_string();
+        match op {
+            Comparison::DoubleEquals | Comparison::NotEquals => {
+                let _fn = self.trait_method(&left, "Eq", "eq")?;
+                let eq = self.call_eq(_fn, &left, &right, scope);
+                Some(Value::BooleanValue(eq == (op == Comparison::DoubleEquals)))
+            }
+            Comparison::And | Comparison::Or => None,
+            _ => {
+                let _fn = match self.trait_method(&left, "Ord", "cmp") {
+                    Some(_fn) => _fn,
+                    None => panic!("Invalid Comparison: {type_name} does not implement Ord"),
+                };
+                let ord = self.call_cmp(_fn, &left, &right, scope);
+                Some(Value::BooleanValue(match op {
+                    Comparison::LessThan => ord.is_lt(),
+                    Comparison::LessThanEq => ord.is_le(),
+                    Comparison::GreaterThan => ord.is_gt(),
+                    _ => ord.is_ge(),
+                }))
+            }
+        }
+    }
+
+    fn call_eq(
+        &mut self,
+        _fn: Rc<RefCell<FunctionDeclarationNode>>,
+        left: &Value,
+        right: &Value,
+        scope: &mut ScopePtr,
+    ) -> bool {
+        let type_name = left.type_name().to_string();
+        match self.call_function(_fn, vec![left.clone(), right.clone()], vec![], scope) {
+            Value::BooleanValue(_eq) => _eq,
+            _ => panic!("{type_name}.eq must return a Boolean"),
+        }
+    }
+
+    fn call_cmp(
+        &mut self,
+        _fn: Rc<RefCell<FunctionDeclarationNode>>,
+        left: &Value,
+        right: &Value,
+        scope: &mut ScopePtr,
+    ) -> Ordering {
+        let type_name = left.type_name().to_string();
+        match self.call_function(_fn, vec![left.clone(), right.clone()], vec![], scope) {
+            Value::IntValue(_i) => _i.cmp(&0),
+            _ => panic!("{type_name}.cmp must return an Int"),
+        }
+    }
+
+    // Equality that goes through Eq for instances implementing it, also inside
+    // Lists, Tuples, Map values and fields. Map keys and Set elements are
+    // hashed, so they always compare structurally
+    fn equals(&mut self, left: &Value, right: &Value, scope: &mut ScopePtr) -> bool {
+        match (left, right) {
+            (Value::InstanceValue(l0), Value::InstanceValue(r0)) => {
+                if let Some(_fn) = self.trait_method(left, "Eq", "eq") {
+                    return self.call_eq(_fn, left, right, scope);
+                }
+                l0.type_name == r0.type_name
+                    && l0.fields.len() == r0.fields.len()
+                    && l0
+                        .fields
+                        .iter()
+                        .zip(&r0.fields)
+                        .all(|((lk, lv), (rk, rv))| lk == rk && self.equals(lv, rv, scope))
+            }
+            (Value::InstanceValue(_), _) => match self.trait_method(left, "Eq", "eq") {
+                Some(_fn) => self.call_eq(_fn, left, right, scope),
+                None => false,
+            },
+            (Value::ListValue(l0), Value::ListValue(r0)) => {
+                let (l0, r0) = (l0.borrow().clone(), r0.borrow().clone());
+                self.equals_all(&l0, &r0, scope)
+            }
+            (Value::TupleValue(l0), Value::TupleValue(r0)) => self.equals_all(l0, r0, scope),
+            (Value::MapValue(l0), Value::MapValue(r0)) => {
+                let (l0, r0) = (l0.borrow().clone(), r0.borrow().clone());
+                l0.len() == r0.len()
+                    && l0.iter().all(|(k, v)| match r0.get(k) {
+                        Some(_v) => self.equals(v, _v, scope),
+                        None => false,
+                    })
+            }
+            _ => left == right,
+        }
+    }
+
+    fn equals_all(&mut self, left: &[Value], right: &[Value], scope: &mut ScopePtr) -> bool {
+        left.len() == right.len()
+            && left
+                .iter()
+                .zip(right)
+                .all(|(l, r)| self.equals(l, r, scope))
+    }
+
+    // Ordering that goes through Ord for instances implementing it, also
+    // inside Lists and Tuples. Other values use their structural order
+    fn compare(&mut self, left: &Value, right: &Value, scope: &mut ScopePtr) -> Ordering {
+        match (left, right) {
+            (Value::InstanceValue(_), _) => match self.trait_method(left, "Ord", "cmp") {
+                Some(_fn) => self.call_cmp(_fn, left, right, scope),
+                None => left.cmp(right),
+            },
+            (Value::ListValue(l0), Value::ListValue(r0)) => {
+                let (l0, r0) = (l0.borrow().clone(), r0.borrow().clone());
+                self.compare_all(&l0, &r0, scope)
+            }
+            (Value::TupleValue(l0), Value::TupleValue(r0)) => self.compare_all(l0, r0, scope),
+            _ => left.cmp(right),
+        }
+    }
+
+    fn compare_all(&mut self, left: &[Value], right: &[Value], scope: &mut ScopePtr) -> Ordering {
+        for (l, r) in left.iter().zip(right) {
+            match self.compare(l, r, scope) {
+                Ordering::Equal => {}
+                ord => return ord,
+            }
+        }
+        left.len().cmp(&right.len())
+    }
+
+    // sort(xs) orders the List in place and min/max pick from their arguments,
+    // all through Ord for instances. Values of different types never fail to compare
+    fn call_ordered(&mut self, id: &str, vals: Vec<Value>, scope: &mut ScopePtr) -> Value {
+        match (id, &vals[..]) {
+            ("sort", [Value::ListValue(_l)]) => {
+                let mut values = _l.take();
+                values.sort_by(|l, r| self.compare(l, r, scope));
+                *_l.borrow_mut() = values;
+                Value::None
+            }
+            ("sort", _) => panic!("sort expects a List"),
+            (_, []) => panic!("Expected at least 1 argument found 0"),
+            _ => {
+                let mut vals = vals.into_iter();
+                let mut res = vals.next().unwrap();
+                for _v in vals {
+                    let ord = self.compare(&_v, &res, scope);
+                    if (id == "min" && ord.is_lt()) || (id == "max" && ord.is_gt()) {
+                        res = _v;
+                    }
+                }
+                res
+            }
+        }
+    }
+
+    // The method `name` of an instance whose type implements `trait_id`
+    fn trait_method(
+        &self,
+        value: &Value,
+        trait_id: &str,
+        name: &str,
+    ) -> Option<Rc<RefCell<FunctionDeclarationNode>>> {
+        match value {
+            Value::InstanceValue(_i) => {
+                let user_type = self.types.get(&_i.type_name)?;
+                if user_type.traits.contains(trait_id) {
+                    user_type.methods.get(name).cloned()
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Replaces instances implementing Display with the String they display as
+    fn displayed(&mut self, value: Value, scope: &mut ScopePtr) -> Value {
+        match value {
+            Value::InstanceValue(_) => match self.display_instance(&value, scope) {
+                Some(_s) => Value::StringValue(_s),
+                None => value,
+            },
+            Value::ListValue(_l) => {
+                let values = _l.borrow().clone();
+                Value::list(
+                    values
+                        .into_iter()
+                        .map(|_v| self.displayed(_v, scope))
+                        .collect(),
+                )
+            }
+            Value::TupleValue(_t) => {
+                Value::TupleValue(_t.into_iter().map(|_v| self.displayed(_v, scope)).collect())
+            }
+            Value::SetValue(_s) => {
+                Value::SetValue(_s.into_iter().map(|_v| self.displayed(_v, scope)).collect())
+            }
+            Value::MapValue(_m) => {
+                let entries = _m.borrow().clone();
+                Value::map(
+                    entries
+                        .into_iter()
+                        .map(|(_k, _v)| (self.displayed(_k, scope), self.displayed(_v, scope)))
+                        .collect(),
+                )
+            }
+            _ => value,
+        }
+    }
+
+    // What an instance implementing Display displays as
+    fn display_instance(&mut self, value: &Value, scope: &mut ScopePtr) -> Option<String> {
+        let _fn = self.trait_method(value, "Display", "display")?;
+        let type_name = value.type_name().to_string();
+        match self.call_function(_fn, vec![value.clone()], vec![], scope) {
+            Value::StringValue(_s) => Some(_s),
+            _ => panic!("{type_name}.display must return a String"),
+        }
+    }
+
+    // Text of the value with instances written through Display
+    fn show(&mut self, value: &Value, repr: bool, scope: &mut ScopePtr) -> String {
+        value.render(repr, &mut |_v| self.display_instance(_v, scope))
+    }
+
+    // repr(value) and pprint(value, width) need Display, so they can't be
+    // plain builtins
+    fn call_repr(&mut self, id: &str, vals: Vec<Value>, scope: &mut ScopePtr) -> Value {
+        match (id, &vals[..]) {
+            ("repr", [_v]) => Value::StringValue(self.show(_v, true, scope)),
+            ("pprint", [_v]) | ("pprint", [_v, Value::IntValue(_)]) => {
+                let width = match vals.get(1) {
+                    Some(Value::IntValue(_w)) if *_w > 0 => *_w as usize,
+                    Some(_) => panic!("pprint: width must be positive"),
+                    None => 80,
+                };
+                println!(
+                    "{}",
+                    _v.pretty(width, &mut |_v| self.display_instance(_v, scope))
+                );
+                Value::None
+            }
+            ("repr", _) => panic!("repr expects 1 argument, found {}", vals.len()),
+            _ => panic!("pprint expects a value and an optional Int width"),
+        }
     }
 
     fn and(&self, left: Value, right: Value) -> Value {
@@ -223,6 +834,7 @@ impl Interpreter {
                 Value::BooleanValue(_right) => Value::BooleanValue(_left && _right),
                 _ => panic!("Invalid Operands"),
             },
+            Value::SetValue(_) => self.perform_op(left, right, Arithmetic::BitAnd),
             _ => panic!("Invalid Operands"),
         }
     }
@@ -233,6 +845,7 @@ impl Interpreter {
                 Value::BooleanValue(_right) => Value::BooleanValue(*_left || *_right),
                 _ => panic!("Invalid Operands"),
             },
+            Value::SetValue(_) => self.perform_op(left, right, Arithmetic::BitOr),
             Value::StringValue(_left) => match &right {
                 Value::StringValue(_right) => {
                     if _left.is_empty() {
@@ -245,7 +858,7 @@ impl Interpreter {
             },
             Value::ListValue(_left) => match &right {
                 Value::ListValue(_right) => {
-                    if _left.is_empty() {
+                    if _left.borrow().is_empty() {
                         right
                     } else {
                         left
@@ -260,47 +873,65 @@ impl Interpreter {
     fn perform_op(&self, left: Value, right: Value, op: Arithmetic) -> Value {
         match left {
             Value::IntValue(l) => match right {
-                Value::IntValue(r) => match op {
-                    Arithmetic::Addition => Value::IntValue(l + r),
-                    Arithmetic::Subtraction => Value::IntValue(l - r),
-                    Arithmetic::Multiply => Value::IntValue(l * r),
-                    Arithmetic::Power => Value::IntValue(l.pow(r as u32)),
-                    Arithmetic::Modulus => Value::IntValue(l % r),
-                    Arithmetic::Divide => {
-                        let res = l as f64 / r as f64;
-                        if res.fract() == 0.0 {
-                            Value::IntValue(res as i64)
-                        } else {
-                            Value::DoubleValue(OrderedFloat(res))
-                        }
-                    }
-                    Arithmetic::TildeDivide => {
-                        Value::IntValue((l as f64 / r as f64).floor() as i64)
-                    }
-                    Arithmetic::PowerDivide => Value::IntValue((l as f64 / r as f64).ceil() as i64),
-                },
+                Value::IntValue(r) => self.perform_int_op(l, r, op),
+                Value::BigIntValue(r) => self.perform_big_int_op(BigInt::from(l), r, op),
+                Value::RationalValue(r) => {
+                    self.perform_rational_op(BigRational::from_integer(BigInt::from(l)), r, op)
+                }
                 Value::DoubleValue(r) => match op {
                     Arithmetic::Addition => Value::DoubleValue(OrderedFloat(l as f64 + r.0)),
                     Arithmetic::Subtraction => Value::DoubleValue(OrderedFloat(l as f64 - r.0)),
                     Arithmetic::Multiply => Value::DoubleValue(OrderedFloat(l as f64 * r.0)),
                     Arithmetic::Divide => Value::DoubleValue(OrderedFloat(l as f64 / r.0)),
                     Arithmetic::Power => Value::DoubleValue(OrderedFloat((l as f64).powf(r.0))),
-                    Arithmetic::Modulus => Value::DoubleValue(OrderedFloat(l as f64 % r.0)),
-                    Arithmetic::TildeDivide => Value::IntValue((l as f64 / r.0).floor() as i64),
-                    Arithmetic::PowerDivide => Value::IntValue((l as f64 / r.0).ceil() as i64),
+                    Arithmetic::Modulus => Value::DoubleValue(Self::double_mod(l as f64, r.0)),
+                    Arithmetic::TildeDivide => Self::double_to_int((l as f64 / r.0).floor()),
+                    Arithmetic::PowerDivide => Self::double_to_int((l as f64 / r.0).ceil()),
+                    _ => panic!("Invalid Operands: '{}' expects Int operands", op),
                 },
                 _ => panic!("Invalid Operands"),
             },
+            Value::BigIntValue(l) => match right {
+                Value::IntValue(r) => self.perform_big_int_op(l, BigInt::from(r), op),
+                Value::BigIntValue(r) => self.perform_big_int_op(l, r, op),
+                Value::RationalValue(r) => {
+                    self.perform_rational_op(BigRational::from_integer(l), r, op)
+                }
+                Value::DoubleValue(_) => {
+                    self.perform_op(Value::DoubleValue(Self::big_int_to_double(&l)), right, op)
+                }
+                _ => panic!("Invalid Operands"),
+            },
+            Value::RationalValue(l) => match right {
+                Value::DoubleValue(_) => {
+                    self.perform_op(Value::DoubleValue(Self::rational_to_double(&l)), right, op)
+                }
+                _ => match right.to_rational() {
+                    Some(r) => self.perform_rational_op(l, r, op),
+                    None => panic!("Invalid Operands"),
+                },
+            },
             Value::DoubleValue(l) => match right {
+                Value::RationalValue(r) => self.perform_op(
+                    Value::DoubleValue(l),
+                    Value::DoubleValue(Self::rational_to_double(&r)),
+                    op,
+                ),
+                Value::BigIntValue(r) => self.perform_op(
+                    Value::DoubleValue(l),
+                    Value::DoubleValue(Self::big_int_to_double(&r)),
+                    op,
+                ),
                 Value::IntValue(r) => match op {
                     Arithmetic::Addition => Value::DoubleValue(OrderedFloat(l.0 + r as f64)),
                     Arithmetic::Subtraction => Value::DoubleValue(OrderedFloat(l.0 - r as f64)),
                     Arithmetic::Multiply => Value::DoubleValue(OrderedFloat(l.0 * r as f64)),
                     Arithmetic::Divide => Value::DoubleValue(OrderedFloat(l.0 / r as f64)),
                     Arithmetic::Power => Value::DoubleValue(OrderedFloat((l.0).powf(r as f64))),
-                    Arithmetic::Modulus => Value::DoubleValue(OrderedFloat(l.0 % r as f64)),
-                    Arithmetic::TildeDivide => Value::IntValue((l.0 / r as f64).floor() as i64),
-                    Arithmetic::PowerDivide => Value::IntValue((l.0 / r as f64).ceil() as i64),
+                    Arithmetic::Modulus => Value::DoubleValue(Self::double_mod(l.0, r as f64)),
+                    Arithmetic::TildeDivide => Self::double_to_int((l.0 / r as f64).floor()),
+                    Arithmetic::PowerDivide => Self::double_to_int((l.0 / r as f64).ceil()),
+                    _ => panic!("Invalid Operands: '{}' expects Int operands", op),
                 },
                 Value::DoubleValue(r) => match op {
                     Arithmetic::Addition => Value::DoubleValue(OrderedFloat(l.0 + r.0)),
@@ -308,21 +939,19 @@ impl Interpreter {
                     Arithmetic::Multiply => Value::DoubleValue(OrderedFloat(l.0 * r.0)),
                     Arithmetic::Divide => Value::DoubleValue(OrderedFloat(l.0 / r.0)),
                     Arithmetic::Power => Value::DoubleValue(OrderedFloat((l.0).powf(r.0))),
-                    Arithmetic::Modulus => Value::DoubleValue(OrderedFloat(l.0 % r.0)),
-                    Arithmetic::TildeDivide => Value::IntValue((l / r.0).floor() as i64),
-                    Arithmetic::PowerDivide => Value::IntValue((l / r.0).ceil() as i64),
+                    Arithmetic::Modulus => Value::DoubleValue(Self::double_mod(l.0, r.0)),
+                    Arithmetic::TildeDivide => Self::double_to_int((l.0 / r.0).floor()),
+                    Arithmetic::PowerDivide => Self::double_to_int((l.0 / r.0).ceil()),
+                    _ => panic!("Invalid Operands: '{}' expects Int operands", op),
                 },
                 _ => panic!("Invalid Operands"),
             },
             Value::StringValue(l) => match right {
                 Value::IntValue(r) => match op {
-                    Arithmetic::Multiply => {
-                        let mut res = String::new();
-                        for _ in 0..r {
-                            res.push_str(l.as_str());
-                        }
-                        Value::StringValue(res)
+                    Arithmetic::Multiply if r < 0 => {
+                        panic!("Invalid Operands: can't repeat a String {r} times")
                     }
+                    Arithmetic::Multiply => Value::StringValue(l.repeat(r as usize)),
                     _ => panic!("Invalid Operands"),
                 },
                 Value::StringValue(r) => match op {
@@ -334,17 +963,22 @@ impl Interpreter {
             },
             Value::ListValue(l) => match right {
                 Value::IntValue(i) => match op {
+                    Arithmetic::Multiply if i < 0 => {
+                        panic!("Invalid Operands: can't repeat a List {i} times")
+                    }
                     Arithmetic::Multiply => {
                         let mut res = vec![];
                         for _ in 0..i {
-                            res.extend(l.clone());
+                            res.extend(l.borrow().iter().cloned());
                         }
-                        Value::ListValue(res)
+                        Value::list(res)
                     }
                     _ => panic!("Invalid Operands"),
                 },
                 Value::ListValue(r) => match op {
-                    Arithmetic::Addition => Value::ListValue([l, r].concat()),
+                    Arithmetic::Addition => {
+                        Value::list([l.borrow().as_slice(), r.borrow().as_slice()].concat())
+                    }
                     _ => panic!("Invalid Operands"),
                 },
                 _ => panic!("Invalid Operands"),
@@ -352,20 +986,225 @@ impl Interpreter {
             Value::MapValue(l) => match right {
                 Value::MapValue(r) => match op {
                     Arithmetic::Addition => {
-                        let mut lc = l;
-                        for (k, v) in r.iter() {
+                        let mut lc = l.borrow().clone();
+                        for (k, v) in r.borrow().iter() {
                             lc.insert(k.clone(), v.clone());
                         }
-                        Value::MapValue(lc)
+                        Value::map(lc)
                     }
                     _ => panic!("Invalid Operands"),
                 },
                 _ => panic!("Invalid Operands"),
             },
+            Value::SetValue(l) => match right {
+                Value::SetValue(r) => Value::SetValue(match op {
+                    Arithmetic::BitOr => &l | &r,
+                    Arithmetic::BitAnd => &l & &r,
+                    Arithmetic::Subtraction => &l - &r,
+                    Arithmetic::BitXor => &l ^ &r,
+                    _ => panic!("Invalid Operands: '{}' is not a Set operation", op),
+                }),
+                _ => panic!("Invalid Operands"),
+            },
             _ => panic!("Invalid Operands"),
         }
     }
 
+    // Int arithmetic, promoting to BigInt when the result overflows i64
+    fn perform_int_op(&self, l: i64, r: i64, op: Arithmetic) -> Value {
+        let res = match op {
+            Arithmetic::Addition => l.checked_add(r),
+            Arithmetic::Subtraction => l.checked_sub(r),
+            Arithmetic::Multiply => l.checked_mul(r),
+            Arithmetic::Power if r < 0 => {
+                return Value::DoubleValue(OrderedFloat((l as f64).powf(r as f64)))
+            }
+            Arithmetic::Power => u32::try_from(r).ok().and_then(|r| l.checked_pow(r)),
+            Arithmetic::Modulus
+            | Arithmetic::Divide
+            | Arithmetic::TildeDivide
+            | Arithmetic::PowerDivide
+                if r == 0 =>
+            {
+                panic!("Division by zero")
+            }
+            // checked_div is None only for i64::MIN / -1, which needs a BigInt
+            Arithmetic::Modulus => l.checked_div(r).map(|_| l.mod_floor(&r)),
+            Arithmetic::Divide => match l.checked_rem(r) {
+                Some(0) => l.checked_div(r),
+                Some(_) => return Value::DoubleValue(Self::ratio_to_double(l.into(), r.into())),
+                None => None,
+            },
+            Arithmetic::TildeDivide => l.checked_div(r).map(|_| Integer::div_floor(&l, &r)),
+            Arithmetic::PowerDivide => l.checked_div(r).map(|_| Integer::div_ceil(&l, &r)),
+            Arithmetic::BitAnd => Some(l & r),
+            Arithmetic::BitOr => Some(l | r),
+            Arithmetic::BitXor => Some(l ^ r),
+            Arithmetic::ShiftLeft => match Self::shift_amount(r) {
+                Some(r) if (l << r) >> r == l => Some(l << r),
+                _ if l == 0 => Some(0),
+                _ => None,
+            },
+            Arithmetic::ShiftRight => match Self::shift_amount(r) {
+                Some(r) => Some(l >> r),
+                None => Some(l >> 63),
+            },
+        };
+        match res {
+            Some(res) => Value::IntValue(res),
+            None => self.perform_big_int_op(BigInt::from(l), BigInt::from(r), op),
+        }
+    }
+
+    fn perform_big_int_op(&self, l: BigInt, r: BigInt, op: Arithmetic) -> Value {
+        let division = matches!(
+            op,
+            Arithmetic::Modulus
+                | Arithmetic::Divide
+                | Arithmetic::TildeDivide
+                | Arithmetic::PowerDivide
+        );
+        if division && r.is_zero() {
+            panic!("Division by zero")
+        }
+        Value::from_big_int(match op {
+            Arithmetic::Addition => l + r,
+            Arithmetic::Subtraction => l - r,
+            Arithmetic::Multiply => l * r,
+            Arithmetic::Power => match r.to_u32() {
+                Some(exp) => l.pow(exp),
+                None if r.is_negative() => {
+                    return Value::DoubleValue(OrderedFloat(
+                        Self::big_int_to_double(&l).powf(Self::big_int_to_double(&r).0),
+                    ))
+                }
+                // 0, 1 and -1 stay small whatever the exponent
+                None if l.abs() <= BigInt::from(1) => {
+                    if r.is_odd() {
+                        l
+                    } else {
+                        l.abs()
+                    }
+                }
+                None => panic!("Invalid Operands: exponent {} is too large", r),
+            },
+            Arithmetic::Modulus => l.mod_floor(&r),
+            Arithmetic::Divide => {
+                if (&l % &r).is_zero() {
+                    l / r
+                } else {
+                    return Value::DoubleValue(Self::ratio_to_double(l, r));
+                }
+            }
+            Arithmetic::TildeDivide => l.div_floor(&r),
+            Arithmetic::PowerDivide => Integer::div_ceil(&l, &r),
+            Arithmetic::BitAnd => l & r,
+            Arithmetic::BitOr => l | r,
+            Arithmetic::BitXor => l ^ r,
+            Arithmetic::ShiftLeft => {
+                if r.is_negative() {
+                    panic!("Invalid Operands: negative shift amount {}", r)
+                }
+                l << r
+                    .to_usize()
+                    .expect("Invalid Operands: shift amount is too large")
+            }
+            Arithmetic::ShiftRight => {
+                if r.is_negative() {
+                    panic!("Invalid Operands: negative shift amount {}", r)
+                }
+                match r.to_usize() {
+                    Some(r) => l >> r,
+                    None if l.is_negative() => BigInt::from(-1),
+                    None => BigInt::zero(),
+                }
+            }
+        })
+    }
+
+    // Exact arithmetic, used whenever a Rational meets an Int or a Rational
+    fn perform_rational_op(&self, l: BigRational, r: BigRational, op: Arithmetic) -> Value {
+        let division = matches!(
+            op,
+            Arithmetic::Modulus
+                | Arithmetic::Divide
+                | Arithmetic::TildeDivide
+                | Arithmetic::PowerDivide
+        );
+        if division && r.is_zero() {
+            panic!("Division by zero")
+        }
+        Value::from_rational(match op {
+            Arithmetic::Addition => l + r,
+            Arithmetic::Subtraction => l - r,
+            Arithmetic::Multiply => l * r,
+            Arithmetic::Divide => l / r,
+            Arithmetic::Power => match r.to_integer().to_i32() {
+                Some(exp) if exp < 0 && l.is_zero() => panic!("Division by zero"),
+                Some(exp) if r.is_integer() => l.pow(exp),
+                _ => {
+                    return Value::DoubleValue(OrderedFloat(
+                        Self::rational_to_double(&l).powf(Self::rational_to_double(&r).0),
+                    ))
+                }
+            },
+            Arithmetic::Modulus => {
+                let q = (&l / &r).floor();
+                l - q * r
+            }
+            Arithmetic::TildeDivide => (l / r).floor(),
+            Arithmetic::PowerDivide => (l / r).ceil(),
+            _ => panic!("Invalid Operands: '{}' expects Int operands", op),
+        })
+    }
+
+    fn rational_to_double(value: &BigRational) -> OrderedFloat<f64> {
+        OrderedFloat(value.to_f64().unwrap_or(f64::NAN))
+    }
+
+    fn big_int_to_double(value: &BigInt) -> OrderedFloat<f64> {
+        OrderedFloat(value.to_f64().unwrap_or(f64::NAN))
+    }
+
+    // Nearest Double to l / r, dividing the Doubles is only exact below 2^53
+    fn ratio_to_double(l: BigInt, r: BigInt) -> OrderedFloat<f64> {
+        match (l.to_i64(), r.to_i64()) {
+            (Some(l), Some(r)) if l.unsigned_abs() < 1 << 53 && r.unsigned_abs() < 1 << 53 => {
+                OrderedFloat(l as f64 / r as f64)
+            }
+            _ => Self::rational_to_double(&BigRational::new(l, r)),
+        }
+    }
+
+    // Remainder with the sign of the divisor, like the Int one
+    fn double_mod(l: f64, r: f64) -> OrderedFloat<f64> {
+        let rem = l % r;
+        if rem != 0.0 && (rem < 0.0) != (r < 0.0) {
+            OrderedFloat(rem + r)
+        } else {
+            OrderedFloat(rem)
+        }
+    }
+
+    // Result of ~/ or ^/ on Doubles, which has no Int form when it's NaN or infinite
+    fn double_to_int(value: f64) -> Value {
+        match value.to_i64() {
+            Some(_i) => Value::IntValue(_i),
+            None => match BigInt::from_f64(value) {
+                Some(_i) => Value::from_big_int(_i),
+                None => panic!("Invalid Operands: {value} has no Int value"),
+            },
+        }
+    }
+
+    // Shifts by 64 or more bits move every bit out, so they have no Some(amount)
+    fn shift_amount(r: i64) -> Option<u32> {
+        if r < 0 {
+            panic!("Invalid Operands: negative shift amount {}", r)
+        }
+        u32::try_from(r).ok().filter(|r| *r < i64::BITS)
+    }
+
     fn walk_assignment_node(&mut self, node: &mut AssignmentNode, scope: &mut ScopePtr) -> Value {
         let id = &node.id;
         let left = self.walk(
@@ -376,20 +1215,27 @@ impl Interpreter {
         );
         let right = self.walk(&mut node.value, scope);
 
-        scope.borrow_mut().set_symbol(
-            id,
-            match node.assign_type {
-                Assignment::Equals => right,
-                Assignment::PlusEq => self.perform_op(left, right, Arithmetic::Addition),
-                Assignment::MinusEq => self.perform_op(left, right, Arithmetic::Subtraction),
-                Assignment::MultiplyEq => self.perform_op(left, right, Arithmetic::Multiply),
-                Assignment::DivideEq => self.perform_op(left, right, Arithmetic::Divide),
-                Assignment::ModulusEq => self.perform_op(left, right, Arithmetic::Modulus),
-                Assignment::PowerEq => self.perform_op(left, right, Arithmetic::Power),
-                Assignment::TildeDivideEq => self.perform_op(left, right, Arithmetic::TildeDivide),
-                Assignment::PowerDivideEq => self.perform_op(left, right, Arithmetic::PowerDivide),
-            },
-        );
+        let value = match node.assign_type {
+            Assignment::Equals => right,
+            Assignment::PlusEq => self.binary_op(left, right, Arithmetic::Addition, scope),
+            Assignment::MinusEq => self.binary_op(left, right, Arithmetic::Subtraction, scope),
+            Assignment::MultiplyEq => self.binary_op(left, right, Arithmetic::Multiply, scope),
+            Assignment::DivideEq => self.binary_op(left, right, Arithmetic::Divide, scope),
+            Assignment::ModulusEq => self.binary_op(left, right, Arithmetic::Modulus, scope),
+            Assignment::PowerEq => self.binary_op(left, right, Arithmetic::Power, scope),
+            Assignment::TildeDivideEq => {
+                self.binary_op(left, right, Arithmetic::TildeDivide, scope)
+            }
+            Assignment::PowerDivideEq => {
+                self.binary_op(left, right, Arithmetic::PowerDivide, scope)
+            }
+            Assignment::BitAndEq => self.binary_op(left, right, Arithmetic::BitAnd, scope),
+            Assignment::BitOrEq => self.binary_op(left, right, Arithmetic::BitOr, scope),
+            Assignment::BitXorEq => self.binary_op(left, right, Arithmetic::BitXor, scope),
+            Assignment::ShiftLeftEq => self.binary_op(left, right, Arithmetic::ShiftLeft, scope),
+            Assignment::ShiftRightEq => self.binary_op(left, right, Arithmetic::ShiftRight, scope),
+        };
+        scope.borrow_mut().set_symbol(id, value);
         Value::None
     }
 
@@ -411,59 +1257,481 @@ impl Interpreter {
         Value::None
     }
 
+    fn walk_destructure_node(&mut self, node: &mut DestructureNode, scope: &mut ScopePtr) -> Value {
+        let value = self.walk(&mut node.value, scope);
+        self.destructure(&node.pattern, value, scope, node.declare);
+        Value::None
+    }
+
+    fn destructure(
+        &mut self,
+        pattern: &Pattern,
+        value: Value,
+        scope: &mut ScopePtr,
+        declare: bool,
+    ) {
+        match pattern {
+            Pattern::Identifier(id) => {
+                if declare {
+                    scope.borrow_mut().declare_symbol(id.to_owned(), value);
+                } else {
+                    scope.borrow_mut().set_symbol(id, value);
+                }
+            }
+            Pattern::List(_pattern) => self.destructure_sequence(
+                pattern,
+                &_pattern.elements,
+                &_pattern.rest,
+                value,
+                scope,
+                declare,
+            ),
+            Pattern::Tuple(_elements) => {
+                self.destructure_sequence(pattern, _elements, &None, value, scope, declare)
+            }
+            Pattern::Map(_pattern) => {
+                let values = match value {
+                    Value::MapValue(_m) => _m,
+                    _ => panic!(
+                        "Cannot destructure {} into {}: expected a Map",
+                        value.type_name(),
+                        pattern
+                    ),
+                };
+                for (key, _p) in _pattern.entries.iter() {
+                    let found = values
+                        .borrow()
+                        .get(&Value::StringValue(key.to_owned()))
+                        .cloned();
+                    match found {
+                        Some(_v) => self.destructure(_p, _v, scope, declare),
+                        None => panic!("Cannot destructure {}: key '{}' not found", pattern, key),
+                    }
+                }
+            }
+        }
+    }
+
+    // Binds the items of a List or Tuple to the element patterns and rest
+    fn destructure_sequence(
+        &mut self,
+        pattern: &Pattern,
+        elements: &[Pattern],
+        rest: &Option<String>,
+        value: Value,
+        scope: &mut ScopePtr,
+        declare: bool,
+    ) {
+        let type_name = value.type_name().to_string();
+        let mut values = match value {
+            Value::ListValue(_l) => _l.borrow().clone(),
+            Value::TupleValue(_t) => _t,
+            _ => panic!(
+                "Cannot destructure {} into {}: expected a List or Tuple",
+                value.type_name(),
+                pattern
+            ),
+        };
+        let expected = elements.len();
+        if values.len() < expected || (rest.is_none() && values.len() > expected) {
+            panic!(
+                "Cannot destructure {} of length {} into {}",
+                type_name,
+                values.len(),
+                pattern
+            );
+        }
+        let rest_values = values.split_off(expected);
+        for (_p, _v) in elements.iter().zip(values) {
+            self.destructure(_p, _v, scope, declare);
+        }
+        if let Some(id) = rest {
+            self.destructure(
+                &Pattern::Identifier(id.to_owned()),
+                Value::list(rest_values),
+                scope,
+                declare,
+            );
+        }
+    }
+
     fn walk_function_declaration_node(
         &mut self,
         node: &FunctionDeclarationNode,
         scope: &mut ScopePtr,
     ) -> Value {
         let fn_id = &node.id;
+        Self::warn_stale_memo(node);
         scope
             .borrow_mut()
             .declare_function(fn_id.to_owned(), Rc::new(RefCell::new(node.to_owned())));
         Value::None
     }
 
-    fn walk_function_call_node(
-        &mut self,
-        node: &mut FunctionCallNode,
-        scope: &mut ScopePtr,
+    fn walk_struct_declaration_node(&mut self, node: &StructDeclarationNode) -> Value {
+        if self.types.contains_key(&node.id) {
+            panic!("Struct '{}' is already declared", node.id)
+        }
+        let user_type = UserType {
+            fields: node.fields.iter().map(|(_id, _)| _id.clone()).collect(),
+            ..Default::default()
+        };
+        self.types.insert(node.id.clone(), user_type);
+        Value::None
+    }
+
+    fn walk_trait_declaration_node(&mut self, node: &TraitDeclarationNode) -> Value {
+        if self.traits.contains_key(&node.id) {
+            panic!("Trait '{}' is already declared", node.id)
+        }
+        let mut _trait = Trait::default();
+        for method in node.methods.iter() {
+            match method {
+                Node::FunctionSignature(_fns) => _trait.required.push(_fns.id.clone()),
+                Node::FunctionDeclaration(_fnd) => _trait.provided.push(_fnd.clone()),
+                _ => {}
+            }
+        }
+        self.traits.insert(node.id.clone(), _trait);
+        Value::None
+    }
+
+    // Adds the methods to the type, along with the defaults of the trait
+    fn walk_impl_node(&mut self, node: &ImplNode) -> Value {
+        let type_id = &node.type_id;
+        let mut methods = node
+            .methods
+            .iter()
+            .filter_map(|_m| match _m {
+                Node::FunctionDeclaration(_fnd) => Some(_fnd.clone()),
+                _ => None,
+            })
+            .collect::<Vec<FunctionDeclarationNode>>();
+        if let Some(trait_id) = &node.trait_id {
+            let _trait = match self.traits.get(trait_id) {
+                Some(_trait) => _trait,
+                None => panic!("Trait '{trait_id}' not found"),
+            };
+            for required in _trait.required.iter() {
+                if !methods.iter().any(|_m| &_m.id == required) {
+                    panic!("impl {trait_id} for {type_id} is missing method '{required}'")
+                }
+            }
+            for provided in _trait.provided.iter() {
+                if !methods.iter().any(|_m| _m.id == provided.id) {
+                    methods.push(provided.clone());
+                }
+            }
+        }
+
+        let user_type = match self.types.get_mut(type_id) {
+            Some(_type) => _type,
+            None => panic!("Struct '{type_id}' not found"),
+        };
+        for method in methods {
+            Self::warn_stale_memo(&method);
+            if user_type.methods.contains_key(&method.id) {
+                panic!("Method '{}' is already defined for {type_id}", method.id)
+            }
+            user_type
+                .methods
+                .insert(method.id.clone(), Rc::new(RefCell::new(method)));
+        }
+        if let Some(trait_id) = &node.trait_id {
+            user_type.traits.insert(trait_id.clone());
+        }
+        Value::None
+    }
+
+    fn walk_field_access_node(
+        &mut self,
+        node: &mut FieldAccessNode,
+        scope: &mut ScopePtr,
+    ) -> Value {
+        let value = self.walk(&mut node.value, scope);
+        let field = match &value {
+            Value::InstanceValue(_i) => _i.get(&node.field),
+            _ => None,
+        };
+        match field {
+            Some(_v) => _v.clone(),
+            None => panic!("{} has no field '{}'", value.type_name(), node.field),
+        }
+    }
+
+    // Point(1, 2) or Point(x: 1, y: 2)
+    fn construct(&self, id: &String, vals: Vec<Value>, named: Vec<(String, Value)>) -> Value {
+        let fields = &self.types[id].fields;
+        if vals.len() > fields.len() {
+            panic!(
+                "{id} has {} fields but {} values were given",
+                fields.len(),
+                vals.len()
+            )
+        }
+        let mut values: Vec<Option<Value>> = vals.into_iter().map(Some).collect();
+        values.resize(fields.len(), None);
+        for (name, value) in named {
+            let idx = match fields.iter().position(|_f| *_f == name) {
+                Some(_idx) => _idx,
+                None => panic!("{id} has no field '{name}'"),
+            };
+            if values[idx].is_some() {
+                panic!("Field '{name}' of {id} is given twice")
+            }
+            values[idx] = Some(value);
+        }
+        Value::InstanceValue(Instance {
+            type_name: id.clone(),
+            fields: fields
+                .iter()
+                .zip(values)
+                .map(|(_f, _v)| match _v {
+                    Some(_v) => (_f.clone(), _v),
+                    None => panic!("Missing field '{_f}' of {id}"),
+                })
+                .collect(),
+        })
+    }
+
+    fn walk_function_call_node(
+        &mut self,
+        node: &mut FunctionCallNode,
+        scope: &mut ScopePtr,
+    ) -> Value {
+        if matches!(node.id.as_str(), "cache_clear" | "cache_stats")
+            && self.is_builtin(&node.id, scope)
+        {
+            return self.cache_control(node, scope);
+        }
+        let (vals, named) = self.walk_arguments(&mut node.args, scope);
+        self.call_by_name(&node.id, vals, named, scope)
+    }
+
+    // cache_clear(f) and cache_stats(f) take the name of a memoized function
+    fn cache_control(&mut self, node: &FunctionCallNode, scope: &ScopePtr) -> Value {
+        let fn_id = match node.args.as_slice() {
+            [Node::Identifier(_id)] => &_id.value,
+            _ => panic!("{}() takes the name of a function", node.id),
+        };
+        let memo = match scope.borrow().get_function(fn_id).borrow().memo.clone() {
+            Some(_memo) => _memo,
+            None => panic!("Function '{fn_id}' is not memoized"),
+        };
+        let mut memo = memo.borrow_mut();
+        if node.id == "cache_clear" {
+            memo.clear();
+            return Value::None;
+        }
+        let stats = [
+            ("hits", Value::IntValue(memo.hits as i64)),
+            ("misses", Value::IntValue(memo.misses as i64)),
+            ("size", Value::IntValue(memo.len() as i64)),
+            (
+                "max",
+                memo.max
+                    .map_or(Value::None, |_max| Value::IntValue(_max as i64)),
+            ),
+        ];
+        Value::map(
+            stats
+                .into_iter()
+                .map(|(_k, _v)| (Value::StringValue(_k.to_string()), _v))
+                .collect(),
+        )
+    }
+
+    fn walk_pipe_node(&mut self, node: &mut PipeNode, scope: &mut ScopePtr) -> Value {
+        let value = self.walk(&mut node.value, scope);
+        let (mut vals, named) = self.walk_arguments(&mut node.call.args, scope);
+        vals.insert(0, value);
+        self.call_by_name(&node.call.id, vals, named, scope)
+    }
+
+    // value.name(args) looks up a method of the value's type first,
+    // then falls back to name(value, args)
+    fn walk_method_call_node(&mut self, node: &mut MethodCallNode, scope: &mut ScopePtr) -> Value {
+        let value = self.walk(&mut node.value, scope);
+        let (mut vals, named) = self.walk_arguments(&mut node.call.args, scope);
+        let id = &node.call.id;
+        let type_name = value.type_name().to_string();
+        let user_method = self
+            .types
+            .get(&type_name)
+            .and_then(|_type| _type.methods.get(id).cloned());
+        vals.insert(0, value);
+        if let Some(_fn) = user_method {
+            return self.call_function(_fn, vals, named, scope);
+        }
+        if let Some(_fn) = self.methods.get(&format!("{type_name}.{id}")) {
+            if let Some((name, _)) = named.first() {
+                panic!("Method '{id}' does not accept named argument '{name}'")
+            }
+            return (_fn)(vals);
+        }
+        if !self.is_builtin(id, scope) && !scope.borrow().has_function(id) {
+            panic!("{type_name} has no method '{id}'")
+        }
+        self.call_by_name(id, vals, named, scope)
+    }
+
+    fn call_by_name(
+        &mut self,
+        id: &String,
+        vals: Vec<Value>,
+        named: Vec<(String, Value)>,
+        scope: &mut ScopePtr,
     ) -> Value {
         // Builtin Function
-        let id = &node.id;
-        if let Some(_fn) = self.builtin.clone().get(id) {
-            let mut vals = vec![];
-            for arg in node.args.iter_mut() {
-                let val = self.walk(arg, scope);
-                vals.push(val);
+        if self.is_builtin(id, scope) {
+            let vals = match id.as_str() {
+                "print" | "println" => vals
+                    .iter()
+                    .map(|_v| Value::StringValue(self.show(_v, false, scope)))
+                    .collect(),
+                "str" => vals
+                    .into_iter()
+                    .map(|_v| self.displayed(_v, scope))
+                    .collect(),
+                _ => vals,
+            };
+            if let Some((name, _)) = named.first() {
+                panic!("Builtin function '{id}' does not accept named argument '{name}'")
             }
-            (_fn)(vals)
+            match id.as_str() {
+                "repr" | "pprint" => return self.call_repr(id, vals, scope),
+                "sort" | "min" | "max" => return self.call_ordered(id, vals, scope),
+                "cache_clear" | "cache_stats" => {
+                    panic!("{id}() takes the name of a function")
+                }
+                _ => {}
+            }
+            match self.builtin.clone().get(id) {
+                Some(_fn) => (_fn)(vals),
+                None => (self.iter_builtin.clone()[id])(self, vals),
+            }
+        } else if self.is_constructor(id, scope) {
+            self.construct(id, vals, named)
         } else {
+            // AshLang Function
             let _fn = scope.borrow().get_function(id);
+            self.call_function(_fn, vals, named, scope)
+        }
+    }
 
-            // AshLang Function
-            let mut vals = vec![];
-            for arg in node.args.iter_mut() {
-                vals.push(self.walk(arg, scope));
-            }
+    // User functions shadow builtins of the same name
+    fn is_builtin(&self, id: &String, scope: &ScopePtr) -> bool {
+        (self.builtin.contains_key(id)
+            || self.iter_builtin.contains_key(id)
+            || INTERPRETER_BUILTINS.contains(&id.as_str()))
+            && !scope.borrow().has_function(id)
+    }
+
+    fn is_constructor(&self, id: &String, scope: &ScopePtr) -> bool {
+        self.types.contains_key(id) && !scope.borrow().has_function(id)
+    }
 
-            if vals.len() != _fn.borrow().params.len() {
-                panic!("Invalid number of arguments to function")
+    // Evaluates call arguments into positional and named values, expanding spreads
+    fn walk_arguments(
+        &mut self,
+        args: &mut [Node],
+        scope: &mut ScopePtr,
+    ) -> (Vec<Value>, Vec<(String, Value)>) {
+        let mut vals = vec![];
+        let mut named = vec![];
+        for arg in args.iter_mut() {
+            match arg {
+                Node::Spread(_node) => vals.extend(self.walk_spread_node(_node, scope)),
+                Node::NamedArgument(_node) => {
+                    let val = self.walk(&mut _node.value, scope);
+                    named.push((_node.id.to_owned(), val));
+                }
+                _ => vals.push(self.walk(arg, scope)),
             }
+        }
+        (vals, named)
+    }
 
-            // Return Memo Value if CFn
-            if _fn.borrow().memo.is_some() && _fn.borrow().contains_key(&vals) {
-                return _fn.borrow().get_cache(&vals);
+    fn walk_spread_node(&mut self, node: &mut SpreadNode, scope: &mut ScopePtr) -> Vec<Value> {
+        match self.walk(&mut node.value, scope) {
+            Value::ListValue(_l) => _l.borrow().clone(),
+            _v => panic!("Cannot spread {}: expected a List", _v.type_name()),
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        _fn: Rc<RefCell<FunctionDeclarationNode>>,
+        vals: Vec<Value>,
+        named: Vec<(String, Value)>,
+        scope: &mut ScopePtr,
+    ) -> Value {
+        let mut call = TailCall {
+            func: _fn,
+            vals,
+            named,
+            scope: scope.clone(),
+            span: self.span.clone(),
+        };
+        // CFn calls whose result is the result of the whole tail call chain
+        let mut memo_keys = vec![];
+        // Likewise for @trace calls, logged once the chain returns
+        let mut traced = vec![];
+        // A tail call is always an explicit return of the calling function
+        let mut returned = false;
+
+        let res = loop {
+            let _fn = call.func;
+            let mut fn_scope = Scope::new(call.scope);
+            let vals = self.bind_arguments(&_fn.borrow(), call.vals, call.named, &mut fn_scope);
+
+            let id = _fn.borrow().id.clone();
+            if let Some(_deprecated) = _fn.borrow().annotation("deprecated") {
+                // Once for each statement calling it
+                if self.deprecated.insert((id.clone(), call.span.clone())) {
+                    let at = match call.span.get_pos() {
+                        _pos if _pos.is_empty() => String::new(),
+                        _pos => format!(" {_pos}"),
+                    };
+                    match _deprecated.args.first() {
+                        Some(Node::String(_msg)) => {
+                            eprintln!("Warning{at}: '{}' is deprecated: {}", id, _msg.value)
+                        }
+                        _ => eprintln!("Warning{at}: '{}' is deprecated", id),
+                    }
+                }
+            }
+            if _fn.borrow().annotation("trace").is_some() {
+                let args = vals
+                    .iter()
+                    .map(|_v| _v.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                eprintln!("{}-> {}({})", "  ".repeat(self.trace_depth), id, args);
+                self.trace_depth += 1;
+                traced.push(id);
             }
 
-            let mut fn_scope = Scope::new(scope.clone());
+            // Generators run lazily, one yield per resume
+            if _fn.borrow().generator {
+                let stmts = match _fn.borrow().body.as_ref() {
+                    Node::BlockStatement(_node) => _node.value.to_owned(),
+                    _ => panic!("Expected BlockStatement"),
+                };
+                break Value::IteratorValue(Iter::generator(stmts, fn_scope));
+            }
 
-            for (key, value) in _fn.borrow().params.iter().zip(vals.iter()) {
-                fn_scope
-                    .borrow_mut()
-                    .declare_symbol(key.clone(), value.clone());
+            // Return Memo Value if CFn
+            let memo = _fn.borrow().memo.clone();
+            if let Some(_memo) = memo {
+                if let Some(_res) = _memo.borrow_mut().get(&vals) {
+                    break _res;
+                }
+                // The body may change Lists and Maps it was passed, so the key is a copy
+                memo_keys.push((_memo.clone(), vals.iter().map(Value::deep_copy).collect()));
             }
 
-            let mut res = self.walk_block_statement_node(
+            let res = self.walk_block_statement_node(
                 match _fn.borrow().body.to_owned().as_mut() {
                     Node::BlockStatement(ref mut _node) => _node,
                     _ => panic!("Expected BlockStatement"),
@@ -472,46 +1740,145 @@ impl Interpreter {
                 false,
             );
 
-            if let Value::ReturnValue(_ret) = res {
-                res = *_ret;
+            // Run the returned call in place of this one instead of recursing
+            match self.tail_call.take() {
+                Some(next) => {
+                    returned = true;
+                    call = TailCall {
+                        scope: Scope::flatten(&next.scope, &fn_scope),
+                        ..next
+                    }
+                }
+                None => match res {
+                    Value::ReturnValue(_ret) => {
+                        returned = true;
+                        break *_ret;
+                    }
+                    _ => break res,
+                },
             }
+        };
 
-            // Store Memo Value in CFn
-            if _fn.borrow().memo.is_some() {
-                _fn.borrow().set_cache(vals, res.clone());
-            }
+        // Store Memo Value in CFn
+        for (_memo, vals) in memo_keys {
+            _memo.borrow_mut().insert(vals, res.clone());
+        }
+        for id in traced.iter().rev() {
+            self.trace_depth -= 1;
+            eprintln!("{}<- {} = {}", "  ".repeat(self.trace_depth), id, res);
+        }
+        self.returned = returned;
+
+        res
+    }
+
+    // Declares the params of a function in its scope and returns the bound values
+    fn bind_arguments(
+        &mut self,
+        _fn: &FunctionDeclarationNode,
+        vals: Vec<Value>,
+        mut named: Vec<(String, Value)>,
+        fn_scope: &mut ScopePtr,
+    ) -> Vec<Value> {
+        let given = vals.len();
+        let mut vals = vals.into_iter();
+        let mut bound = vec![];
+
+        for param in _fn.params.iter() {
+            let named_idx = named.iter().position(|(id, _)| id == &param.id);
+            let value = if param.rest {
+                if named_idx.is_some() {
+                    panic!(
+                        "Rest parameter '{}' of function '{}' can't be passed by name",
+                        param.id, _fn.id
+                    )
+                }
+                Value::list(vals.by_ref().collect())
+            } else if let Some(val) = vals.next() {
+                if named_idx.is_some() {
+                    panic!(
+                        "Function '{}' got multiple values for argument '{}'",
+                        _fn.id, param.id
+                    )
+                }
+                val
+            } else if let Some(idx) = named_idx {
+                named.remove(idx).1
+            } else if let Some(default) = &param.default {
+                self.walk(&mut default.to_owned(), fn_scope)
+            } else {
+                panic!("Function '{}' missing argument '{}'", _fn.id, param.id)
+            };
+            fn_scope
+                .borrow_mut()
+                .declare_symbol(param.id.to_owned(), value.clone());
+            bound.push(value);
+        }
 
-            res
+        if vals.len() != 0 {
+            panic!(
+                "Function '{}' takes {} positional arguments but {} were given",
+                _fn.id,
+                given - vals.len(),
+                given
+            )
         }
+        if let Some((name, _)) = named.first() {
+            panic!(
+                "Function '{}' got an unexpected argument '{}'",
+                _fn.id, name
+            )
+        }
+        bound
     }
 
     fn walk_while_loop_node(&mut self, node: &mut WhileLoopNode, scope: &mut ScopePtr) -> Value {
-        while match self.walk(&mut node.condition, scope) {
-            Value::BooleanValue(_b) => _b,
-            _ => panic!("Invalid Type in While Condition"),
-        } {
-            let res = self.walk(&mut node.body, scope);
-            if let Value::ReturnValue(_) = res {
-                return res;
-            } else if res == Value::Break {
-                break;
-            } else if res == Value::Continue {
-                continue;
+        // The body of a do-while runs once before the condition is checked
+        let mut skip_check = node.kind == LoopKind::DoWhile;
+        while std::mem::take(&mut skip_check) || self.loop_condition(node, scope) {
+            match self.walk(&mut node.body, scope) {
+                Value::ReturnValue(_r) => return Value::ReturnValue(_r),
+                Value::Break(_label) if !node.is_target(&_label) => return Value::Break(_label),
+                Value::Break(_) => break,
+                Value::Continue(_label) if !node.is_target(&_label) => {
+                    return Value::Continue(_label)
+                }
+                _ => {}
             }
         }
         Value::None
     }
+
+    fn loop_condition(&mut self, node: &mut WhileLoopNode, scope: &mut ScopePtr) -> bool {
+        match self.walk(&mut node.condition, scope) {
+            Value::BooleanValue(_b) => _b,
+            _ => panic!("Invalid Type in While Condition"),
+        }
+    }
+
     fn walk_if_statement_node(
         &mut self,
         node: &mut IfStatementNode,
         scope: &mut ScopePtr,
     ) -> Value {
+        match self.if_branch(node, scope) {
+            Some(block) => self.walk(block, scope),
+            None => Value::None,
+        }
+    }
+
+    // Evaluates the conditions of an if statement and returns the block to run
+    fn if_branch<'a>(
+        &mut self,
+        node: &'a mut IfStatementNode,
+        scope: &mut ScopePtr,
+    ) -> Option<&'a mut Node> {
         // Run If
         if match self.walk(&mut node.condition, scope) {
             Value::BooleanValue(_b) => _b,
             _ => panic!("Invalid Type in If Condition"),
         } {
-            return self.walk(&mut node.true_block, scope);
+            return Some(&mut node.true_block);
         }
 
         // Run Elifs
@@ -522,7 +1889,7 @@ impl Interpreter {
                         Value::BooleanValue(_b) => _b,
                         _ => todo!(),
                     } {
-                        return self.walk(&mut _node.true_block, scope);
+                        return Some(&mut _node.true_block);
                     }
                 }
                 _ => panic!("Elif Node Expected"),
@@ -530,10 +1897,1006 @@ impl Interpreter {
         }
 
         // Run else
-        if let Some(else_block) = node.else_block.as_mut() {
-            return self.walk(else_block, scope);
+        node.else_block.as_deref_mut()
+    }
+
+    // Pulls the next value out of an iterator, None once it is exhausted
+    pub fn iter_next(&mut self, it: &IteratorPtr) -> Option<Value> {
+        let mut it = it
+            .try_borrow_mut()
+            .unwrap_or_else(|_| panic!("Generator is already running"));
+        if let Some(peeked) = it.peeked.take() {
+            return peeked;
+        }
+        match &mut it.source {
+            Source::Values(_values) => _values.next(),
+            Source::Range { next, end, step } => {
+                if (*step > 0 && *next >= *end) || (*step < 0 && *next <= *end) {
+                    return None;
+                }
+                let res = *next;
+                *next = next.saturating_add(*step);
+                Some(Value::IntValue(res))
+            }
+            Source::Generator(_gen) => self.resume_generator(_gen),
+            Source::Take(_inner, remaining) => {
+                if *remaining == 0 {
+                    return None;
+                }
+                *remaining -= 1;
+                self.iter_next(_inner)
+            }
+            Source::Skip(_inner, count) => {
+                while *count > 0 {
+                    *count -= 1;
+                    self.iter_next(_inner)?;
+                }
+                self.iter_next(_inner)
+            }
         }
+    }
 
-        Value::None
+    // Runs a generator until its next yield. Statements that can't reach a
+    // yield are walked as usual, the others are unfolded into frames.
+    fn resume_generator(&mut self, gen: &mut Generator) -> Option<Value> {
+        loop {
+            match gen.frames.last_mut()? {
+                Frame::Block { stmts, idx, scope } => {
+                    if *idx >= stmts.len() {
+                        gen.frames.pop();
+                        continue;
+                    }
+                    let mut stmt = stmts[*idx].to_owned();
+                    let mut scope = scope.clone();
+                    *idx += 1;
+
+                    if !stmt.contains_yield() {
+                        match self.walk(&mut stmt, &mut scope) {
+                            Value::ReturnValue(_) => {
+                                // The returned value is dropped but the call still runs
+                                if let Some(mut call) = self.tail_call.take() {
+                                    self.call_function(
+                                        call.func,
+                                        call.vals,
+                                        call.named,
+                                        &mut call.scope,
+                                    );
+                                }
+                                gen.frames.clear()
+                            }
+                            Value::Break(_label) => Self::unwind_loop(gen, true, &_label),
+                            Value::Continue(_label) => Self::unwind_loop(gen, false, &_label),
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    match stmt {
+                        Node::Yield(mut _node) => {
+                            return Some(self.walk(&mut _node.value, &mut scope))
+                        }
+                        Node::BlockStatement(_node) => gen.frames.push(Frame::Block {
+                            stmts: _node.value,
+                            idx: 0,
+                            scope: Scope::new(scope),
+                        }),
+                        Node::IfStatement(mut _node) => {
+                            if let Some(block) = self.if_branch(&mut _node, &mut scope) {
+                                gen.frames.push(Frame::Block {
+                                    stmts: vec![block.to_owned()],
+                                    idx: 0,
+                                    scope,
+                                });
+                            }
+                        }
+                        Node::WhileLoop(_node) => gen.frames.push(Frame::While {
+                            skip_check: _node.kind == LoopKind::DoWhile,
+                            node: _node,
+                            scope,
+                        }),
+                        _ => panic!("Unexpected yield in {}", stmt),
+                    }
+                }
+                Frame::While {
+                    node,
+                    scope,
+                    skip_check,
+                } => {
+                    let mut scope = scope.clone();
+                    if std::mem::take(skip_check) || self.loop_condition(node, &mut scope) {
+                        let body = node.body.as_ref().to_owned();
+                        gen.frames.push(Frame::Block {
+                            stmts: vec![body],
+                            idx: 0,
+                            scope,
+                        });
+                    } else {
+                        gen.frames.pop();
+                    }
+                }
+            }
+        }
+    }
+
+    // A cached result is only right while the function depends on nothing but
+    // its arguments, so reading other variables is reported once per declaration
+    fn warn_stale_memo(node: &FunctionDeclarationNode) {
+        let memo = match &node.memo {
+            Some(_memo) => _memo,
+            None => return,
+        };
+        if memo.borrow().warned {
+            return;
+        }
+        memo.borrow_mut().warned = true;
+        let mut bound = node.params.iter().map(|_p| _p.id.clone()).collect();
+        let mut free = vec![];
+        node.body.free_variables(&mut bound, &mut free);
+        for var in free {
+            eprintln!(
+                "Warning: cfn '{}' reads '{var}', which isn't a parameter, so its cached results can go stale",
+                node.id
+            );
+        }
+    }
+
+    // Drops the frames inside the targeted loop, and the loop itself on break
+    fn unwind_loop(gen: &mut Generator, is_break: bool, label: &Option<String>) {
+        while let Some(frame) = gen.frames.pop() {
+            if let Frame::While { node, .. } = &frame {
+                if node.is_target(label) {
+                    if !is_break {
+                        gen.frames.push(frame);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Lexer, parser::Parser, values::Value};
+
+    use super::Interpreter;
+
+    fn eval(code: &str) -> Value {
+        let tokens = Lexer::new(code.to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        Interpreter::new(ast).eval()
+    }
+
+    #[test]
+    fn destructure_list_with_rest() {
+        assert_eq!(
+            eval("fn main() { let [a, b, ..rest] = [1, 2, 3, 4]; return [b, a, rest]; }"),
+            eval("fn main() { return [2, 1, [3, 4]]; }")
+        )
+    }
+
+    #[test]
+    fn destructure_map() {
+        assert_eq!(
+            eval(
+                r#"fn main() { let {name, age: a} = {"name": "Ash", "age": 3}; return [name, a]; }"#
+            ),
+            eval(r#"fn main() { return ["Ash", 3]; }"#)
+        )
+    }
+
+    #[test]
+    fn destructure_swap() {
+        assert_eq!(
+            eval("fn main() { let a = 1, b = 2; [a, b] = [b, a]; return [a, b]; }"),
+            eval("fn main() { return [2, 1]; }")
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot destructure List of length 3 into [x, y]")]
+    fn destructure_shape_mismatch() {
+        eval("fn main() { let [x, y] = [1, 2, 3]; }");
+    }
+
+    #[test]
+    fn default_and_named_params() {
+        let code = r#"
+            fn greet(name, greeting = "Hi") { return greeting + " " + name; }
+            fn main() { return [greet("A"), greet("B", "Yo"), greet(name: "C", greeting: "Hey")]; }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(r#"fn main() { return ["Hi A", "Yo B", "Hey C"]; }"#)
+        )
+    }
+
+    #[test]
+    fn rest_params_and_spread() {
+        let code = r#"
+            fn count(first, ..others) { return [first, len(others)]; }
+            fn main() { let xs = [1, 2, 3]; return [count(..xs), [0, ..xs, 4]]; }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval("fn main() { return [[1, 2], [0, 1, 2, 3, 4]]; }")
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Function 'f' takes 1 positional arguments but 2 were given")]
+    fn too_many_arguments() {
+        eval("fn f(a) { return a; } fn main() { f(1, 2); }");
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        assert_eq!(
+            eval("fn main() { let a = 12; a .^.= 1; return [12 .&. 10, 12 .|. 10, a, ~12, 1 << 4, -16 >> 2]; }"),
+            eval("fn main() { return [8, 14, 13, -13, 16, -4]; }")
+        )
+    }
+
+    #[test]
+    fn int_promotes_to_big_int_on_overflow() {
+        let code = r#"
+            fn fact(n) { if (n < 2) { return 1; } return n * fact(n - 1); }
+            fn main() { return [str(fact(25)), fact(25) / fact(24), 2 ^ 64 - 2 ^ 64, 2 ^ 64 > 1]; }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(r#"fn main() { return ["15511210043330985984000000", 25, 0, true]; }"#)
+        )
+    }
+
+    #[test]
+    fn rationals_are_exact() {
+        let code = r#"
+            fn main() {
+                let third = frac(1, 3);
+                return [third + third + third, str(third + frac(1, 6)), str(-frac("6/4")), int(frac(7, 2)), third == frac(2, 6)];
+            }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(r#"fn main() { return [1, "1/2", "-3/2", 3, true]; }"#)
+        )
+    }
+
+    #[test]
+    fn strings_index_by_code_point() {
+        let code = r#"
+            fn main() {
+                let café_au_lait = "héllo wörld";
+                return [len(café_au_lait), get(café_au_lait, 7), set(café_au_lait, 1, "e"), pop(café_au_lait, 7), slice(café_au_lait, 6), slice([1, 2, 3], 1, 2)];
+            }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(r#"fn main() { return [11, "ö", "hello wörld", "héllo wrld", "wörld", [2]]; }"#)
+        )
+    }
+
+    #[test]
+    fn generators_are_lazy() {
+        let code = r#"
+            fn naturals() {
+                let n = 0;
+                while (true) {
+                    n += 1;
+                    if (n % 2 == 0) { continue; }
+                    yield n;
+                }
+            }
+            fn until(it, limit) {
+                while (has_next(it)) {
+                    let x = next(it);
+                    if (x > limit) { break; }
+                    yield x;
+                }
+                yield "done";
+            }
+            fn main() {
+                let odds = naturals();
+                let first = next(odds);
+                return [first, collect(take(skip(odds, 1), 3)), collect(until(range(5), 2)), next(range(0), -1)];
+            }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(r#"fn main() { return [1, [5, 7, 9], [0, 1, 2, "done"], -1]; }"#)
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Iterator is exhausted")]
+    fn next_on_exhausted_iterator() {
+        eval("fn main() { let it = iter([1]); next(it); next(it); }");
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_stack() {
+        let code = r#"
+            fn count(n, acc) { if (n == 0) { return acc; } return count(n - 1, acc + 1); }
+            fn is_even(n) { if (n == 0) { return true; } return is_odd(n - 1); }
+            fn is_odd(n) { if (n == 0) { return false; } return is_even(n - 1); }
+            cfn steps(n) { if (n == 0) { return 0; } return steps(n - 1); }
+            fn main() { return [count(100000, 0), is_even(100001), steps(50000), steps(50000)]; }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval("fn main() { return [100000, false, 0, 0]; }")
+        )
+    }
+
+    #[test]
+    fn pipe_passes_value_as_first_argument() {
+        let code = r#"
+            fn shout(s, suffix = "!") { return s + suffix; }
+            fn main() {
+                let s = "  hello pipe world ";
+                return [s |> trim() |> split(" ") |> join("_") |> shout, (1 + 2 |> str()) == "3", [1, 2] |> len |> shout(suffix: 0)];
+            }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(r#"fn main() { return ["hello_pipe_world!", true, 2]; }"#)
+        )
+    }
+
+    #[test]
+    fn user_functions_shadow_builtins() {
+        assert_eq!(
+            eval(
+                r#"fn trim(s) { return "custom"; } fn main() { return [trim(" a "), " a " |> trim]; }"#
+            ),
+            eval(r#"fn main() { return ["custom", "custom"]; }"#)
+        )
+    }
+
+    #[test]
+    fn comprehensions() {
+        let code = r#"
+            fn main() {
+                let x = "outer";
+                let xs = [1, 2, 3, 4, 5, 6];
+                let squares = [x * x for x in xs if x % 2 == 0];
+                let pairs = [[a, b] for a in range(3) for b in range(a) if a + b > 1];
+                let swapped = {v: k for k, v in {"a": 1, "b": 2}};
+                return [squares, pairs, swapped, x];
+            }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(
+                r#"fn main() { return [[4, 16, 36], [[2, 0], [2, 1]], {1: "a", 2: "b"}, "outer"]; }"#
+            )
+        )
+    }
+
+    #[test]
+    fn sets() {
+        let code = r#"
+            fn main() {
+                let a = #{1, 2, 3, 2};
+                let b = to_set([3, 4, 3]);
+                let keyed = {#{1, 2}: "found"};
+                return [len(a), a | b, a & b, a - b, #{1} < a, has(b, 4), #{3, 2, 1} == a, get(keyed, #{2, 1})];
+            }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(
+                r#"fn main() { return [3, #{1, 2, 3, 4}, #{3}, #{1, 2}, true, true, true, "found"]; }"#
+            )
+        )
+    }
+
+    #[test]
+    fn tuples_and_multiple_returns() {
+        let code = r#"
+            fn min_max(xs) { return min(..xs), max(..xs); }
+            cfn area(size) { let w, h = size; return w * h; }
+            fn main() {
+                let q, r = divmod(-7, 2);
+                let (lo, hi) = min_max([3, 1, 2]);
+                let grid = {(0, 1): "a"};
+                return [q, r, lo, hi, (1), (1,), get(grid, (0, 1)), area((2, 3)), area((2, 3)), len(())];
+            }
+        "#;
+        assert_eq!(
+            eval(code),
+            eval(r#"fn main() { return [-4, 1, 1, 3, 1, tuple([1]), "a", 6, 6, 0]; }"#)
+        )
+    }
+
+    #[test]
+    fn labeled_loops() {
+        assert_eq!(
+            eval(
+                "fn main() {
+                    let res = [], i = 0;
+                    outer: while (i < 5) {
+                        i += 1;
+                        let j = 0;
+                        loop {
+                            j += 1;
+                            if (j > i) { continue outer; }
+                            if (i * j == 6) { break outer; }
+                            res = res + [[i, j]];
+                        }
+                    }
+                    let k = 10;
+                    do { k += 1; } while (k < 5);
+                    return [res, k];
+                }"
+            ),
+            eval("fn main() { return [[[1, 1], [2, 1], [2, 2], [3, 1]], 11]; }")
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "'inner' is not the label of an enclosing loop")]
+    fn unknown_loop_label() {
+        eval("fn main() { outer: loop { loop { break inner; } } }");
+    }
+
+    #[test]
+    fn method_calls() {
+        assert_eq!(
+            eval(
+                r#"fn scale(xs, by = 2) { return [x * by for x in xs]; }
+                fn main() {
+                    let s = " a,b,c ";
+                    return [s.trim().split(",").len(), [1, 2].scale(by: 3), "Ash".upper(), 1.5];
+                }"#
+            ),
+            eval(r#"fn main() { return [3, [3, 6], "ASH", 1.5]; }"#)
+        )
+    }
+
+    #[test]
+    fn trait_operators() {
+        assert_eq!(
+            eval(
+                r#"struct Point { x, y }
+                trait Shape {
+                    fn area(self);
+                    fn describe(self) { return "area " + str(self.area()); }
+                }
+                impl Add for Point {
+                    fn add(self, other) { return Point(self.x + other.x, self.y + other.y); }
+                }
+                impl Ord for Point {
+                    fn cmp(self, other) { return self.x - other.x; }
+                }
+                impl Display for Point {
+                    fn display(self) { return str(self.x) + "," + str(self.y); }
+                }
+                impl Shape for Point {
+                    fn area(self) { return self.x * self.y; }
+                }
+                fn main() {
+                    let p = Point(1, 2) + Point(y: 3, x: 2);
+                    return [p.x, p.y, p < Point(4, 0), p == Point(3, 5), str(p), p.describe()];
+                }"#
+            ),
+            eval(r#"fn main() { return [3, 5, true, true, "3,5", "area 15"]; }"#)
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "impl Shape for Point is missing method 'area'")]
+    fn trait_missing_method() {
+        eval("struct Point { x, y } trait Shape { fn area(self); } impl Shape for Point {} fn main() {}");
+    }
+
+    #[test]
+    fn script_mode() {
+        let code = "let names = []; fn main(args) { return names + args; } names += [\"ash\"];";
+        let tokens = Lexer::new(code.to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        assert_eq!(
+            Interpreter::new(ast)
+                .with_args(vec!["-v".to_string()])
+                .eval(),
+            eval(r#"fn main() { return ["ash", "-v"]; }"#)
+        );
+        assert_eq!(eval("let x = 1; x += 1;"), Value::None);
+    }
+
+    #[test]
+    #[should_panic(expected = "'return' can only be used inside functions")]
+    fn top_level_return() {
+        eval("if (true) { return 1; }");
+    }
+
+    #[test]
+    fn shared_collections() {
+        assert_eq!(
+            eval(
+                r#"fn fill(xs, m) { push(xs, 1); insert(m, "k", xs); }
+                fn main() {
+                    let xs = [], m = {};
+                    fill(xs, m);
+                    let ys = xs, zs = copy(xs), deep = deepcopy(m);
+                    extend(ys, [2, 3]);
+                    insert(xs, 0, remove(ys, 2));
+                    return [xs, get(m, "k"), zs, deep];
+                }"#
+            ),
+            eval(r#"fn main() { return [[3, 1, 2], [3, 1, 2], [1], {"k": [1]}]; }"#)
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(
+            eval(
+                r#"@memo(max = 50)
+                fn fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }
+                @trace
+                @deprecated("use fib")
+                fn old_fib(n) { return fib(n); }
+                @pure
+                fn main() { return [fib(30), old_fib(10)]; }"#
+            ),
+            eval("fn main() { return [832040, 55]; }")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown annotation '@cache'")]
+    fn unknown_annotation() {
+        eval("@cache fn main() {}");
+    }
+
+    #[test]
+    fn memo_cache() {
+        assert_eq!(
+            eval(
+                r#"fn square_twice(n) {
+                    cfn square(x) { return x * x; }
+                    square(n) + square(n);
+                    return get(cache_stats(square), "hits");
+                }
+                @memo(max = 2)
+                fn double(x) { return x * 2; }
+                fn main() {
+                    let hits = [square_twice(3), square_twice(3)];
+                    let a = [double(1), double(2), double(3), double(1)];
+                    let stats = cache_stats(double);
+                    cache_clear(double);
+                    return [hits, a, get(stats, "misses"), get(stats, "size"), cache_stats(double)];
+                }"#
+            ),
+            eval(
+                r#"fn main() {
+                    return [[1, 3], [2, 4, 6, 2], 4, 2, {"hits": 0, "misses": 0, "size": 0, "max": 2}];
+                }"#
+            )
+        );
+    }
+
+    #[test]
+    fn cache_stats_in_tail_position() {
+        assert_eq!(
+            eval("cfn f(x) { return x; } fn main() { f(1); f(1); return cache_stats(f); }"),
+            eval(
+                r#"fn nothing() {} fn main() { return {"hits": 1, "misses": 1, "size": 1, "max": nothing()}; }"#
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Function 'f' is not memoized")]
+    fn cache_stats_needs_memo() {
+        eval("fn f() {} fn main() { cache_stats(f); }");
+    }
+
+    #[test]
+    fn value_ordering() {
+        assert_eq!(
+            eval(
+                r#"fn nothing() {}
+                fn main() {
+                    let xs = ["b", [1, 2], 2.5, true, "aa", 1, nothing(), [1], frac(1, 2), (1, 2)];
+                    sort(xs);
+                    let m = {1: "one"};
+                    return [
+                        "b" < "aa", [1, 3] > [1, 2, 3], 1 == 1.0, 0.5 == frac(1, 2),
+                        has(m, 1.0), len(#{2, 2.0}), xs, min("a", 1), max(1, 2.0, frac(3, 2))
+                    ];
+                }"#
+            ),
+            eval(
+                r#"fn nothing() {}
+                fn main() {
+                    return [
+                        false, true, true, true, true, 1,
+                        [nothing(), true, frac(1, 2), 1, 2.5, "aa", "b", [1], [1, 2], (1, 2)], 1, 2.0
+                    ];
+                }"#
+            )
+        );
+    }
+
+    #[test]
+    fn ordered_maps() {
+        assert_eq!(
+            eval(
+                r#"fn main() {
+                    let m = {"b": 1, "a": 2, "c": 3};
+                    insert(m, "d", 4);
+                    remove(m, "a");
+                    let pairs = {k: v * 10 for k, v in m};
+                    let a = {1: "x", 2: "y"}, b = {2: "y", 1: "x"};
+                    let merged = m + {"a": 0, "b": 5};
+                    return [keys(m), pairs, keys(merged), get(merged, "b"), len(#{a, b}), a == b];
+                }"#
+            ),
+            eval(
+                r#"fn main() {
+                    return [
+                        ["b", "c", "d"], {"b": 10, "c": 30, "d": 40}, ["b", "c", "d", "a"], 5, 1, true
+                    ];
+                }"#
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate key 1 in map literal")]
+    fn duplicate_map_key() {
+        eval("fn main() { return {1: 1, 2: 2, 1: 3}; }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate key 1 in map literal")]
+    fn duplicate_map_key_by_value() {
+        eval("fn main() { return {1.0: 1, 2: 2, 1: 3}; }");
+    }
+
+    #[test]
+    fn repr_and_pprint() {
+        assert_eq!(
+            eval(
+                r#"struct P { x }
+                impl Display for P { fn display(self) { return "P" + str(self.x); } }
+                fn main() {
+                    return [
+                        repr("a\"b\n"), repr(3.0), repr(["1", 1, 2.5, (1,)]), repr({"k": #{2, 1}}),
+                        repr(P(1)), repr([P(2)]), str(3.0)
+                    ];
+                }"#
+            ),
+            eval(
+                r#"fn main() {
+                    return [
+                        "\"a\\\"b\\n\"", "3.0", "[\"1\", 1, 2.5, (1,)]", "{\"k\": #{1, 2}}",
+                        "P1", "[P2]", "3"
+                    ];
+                }"#
+            )
+        );
+        assert_eq!(
+            eval(r#"fn main() { return [repr(2.0), [1.0].repr()]; }"#),
+            eval(r#"fn main() { return ["2.0", "[1.0]"]; }"#)
+        );
+        assert_eq!(
+            eval("fn main() { return repr(2.0); }"),
+            Value::StringValue(String::from("2.0"))
+        );
+        let value = eval(r#"fn main() { return {"a": [1, 2, 3], "b": [[4, 5], "six"]}; }"#);
+        assert_eq!(
+            value.pretty(20, &mut |_| None),
+            "{\n  \"a\": [1, 2, 3],\n  \"b\": [\n    [4, 5],\n    \"six\"\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn pprint_edge_cases() {
+        let value = eval("struct P { x, y } fn main() { return [P(1, 2), [], (1,)]; }");
+        let items = match value {
+            Value::ListValue(_l) => _l.borrow().clone(),
+            _ => panic!("Expected List"),
+        };
+        let mut show = |_v: &Value| match _v {
+            Value::InstanceValue(_) => Some(String::from("a point shown through Display")),
+            _ => None,
+        };
+        assert_eq!(
+            items[0].pretty(10, &mut show),
+            "a point shown through Display"
+        );
+        assert_eq!(items[1].pretty(1, &mut show), "[]");
+        assert_eq!(items[2].pretty(1, &mut show), "(\n  1,\n)");
+    }
+
+    // Each expression against the repr of its result, which tells Ints and Doubles apart
+    fn conforms(cases: &[(&str, &str)]) {
+        for (expr, expected) in cases {
+            assert_eq!(
+                eval(&format!("fn main() {{ return repr({expr}); }}")),
+                Value::StringValue(expected.to_string()),
+                "{expr}"
+            );
+        }
+    }
+
+    #[test]
+    fn numeric_conformance() {
+        conforms(&[
+            // Int division is exact, a remainder makes it a Double
+            ("6 / 3", "2"),
+            ("7 / 2", "3.5"),
+            ("9007199254740993 / 1", "9007199254740993"),
+            ("9007199254740995 ~/ 1", "9007199254740995"),
+            ("2 ^ 70 / 2 ^ 68", "4"),
+            ("(-9223372036854775807 - 1) / -1", "9223372036854775808"),
+            ("(-9223372036854775807 - 1) % -1", "0"),
+            // ~/ floors, ^/ rounds up and % takes the sign of the divisor
+            ("-7 ~/ 2", "-4"),
+            ("-7 ^/ 2", "-3"),
+            ("-7 % 3", "2"),
+            ("7 % -3", "-2"),
+            ("-(2 ^ 70) % 3", "2"),
+            ("frac(-7, 2) % 2", "1/2"),
+            ("-7.5 % 2", "0.5"),
+            ("7.5 ~/ 2", "3"),
+            ("2.0 ^ 64 ~/ 1", "18446744073709551616"),
+            // Negative exponents give Doubles, Rationals stay exact
+            ("2 ^ -2", "0.25"),
+            ("2.0 ^ 2", "4.0"),
+            ("frac(1, 2) ^ -2", "4"),
+            // Doubles follow IEEE 754, NaN equals itself and sorts above every number
+            ("1.0 / 0", "inf"),
+            ("-1 / 0.0", "-inf"),
+            ("0 ^ -1", "inf"),
+            ("0.0 / 0.0", "NaN"),
+            ("0.0 / 0.0 == 0.0 / 0.0", "true"),
+            ("0.0 / 0.0 > 1.0 / 0", "true"),
+            ("1 == 1.0", "true"),
+            // Repetition
+            (r#""ab" * 2"#, r#""abab""#),
+            (r#""ab" * 0"#, r#""""#),
+            ("[1] * 2", "[1, 1]"),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Division by zero")]
+    fn int_modulus_by_zero() {
+        eval("fn main() { return 1 % 0; }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Division by zero")]
+    fn int_division_by_zero() {
+        eval("fn main() { return 2 ^ 70 / 0; }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Division by zero")]
+    fn rational_floor_division_by_zero() {
+        eval("fn main() { return frac(1, 2) ~/ 0; }");
+    }
+
+    #[test]
+    #[should_panic(expected = "NaN has no Int value")]
+    fn nan_floor_division() {
+        eval("fn main() { return (0.0 / 0.0) ~/ 1; }");
+    }
+
+    #[test]
+    #[should_panic(expected = "can't repeat a String -1 times")]
+    fn negative_string_repeat() {
+        eval(r#"fn main() { return "a" * -1; }"#);
+    }
+
+    #[test]
+    fn keys_are_copied() {
+        assert_eq!(
+            eval(
+                r#"fn main() {
+                    let keys = [[i] for i in range(50)];
+                    let m = {get(keys, i): i for i in range(50)};
+                    let s = #{get(keys, 0)};
+                    let i = 0;
+                    while (i < 50) {
+                        push(get(keys, i), 0);
+                        i += 1;
+                    }
+                    let found = [k for k in range(50) if has(m, [k])];
+                    push(get(keys(m), 0), 1);
+                    return [len(found), len(m), has(s, [0]), has(m, [0]), get(keys, 0)];
+                }"#
+            ),
+            eval("fn main() { return [50, 50, true, true, [0, 0]]; }")
+        );
+    }
+
+    #[test]
+    fn tail_calls_see_caller_scope() {
+        assert_eq!(
+            eval(
+                r#"fn f() { return x; }
+                fn g() { let r = f(); return r; }
+                fn h() { let x = 2; return f(); }
+                fn k() { let x = 3; return g(); }
+                fn main() { return [h(), k()]; }"#
+            ),
+            eval("fn main() { return [2, 3]; }")
+        );
+    }
+
+    fn exit_code(code: &str) -> i32 {
+        let tokens = Lexer::new(code.to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        Interpreter::new(ast).run()
+    }
+
+    #[test]
+    fn exit_code_from_explicit_return() {
+        assert_eq!(exit_code("fn main() { return 3; }"), 3);
+        assert_eq!(
+            exit_code("fn code() { 4; } fn main() { return code(); }"),
+            4
+        );
+        assert_eq!(exit_code("fn main() { let l = [1, 2, 3]; len(l); }"), 0);
+        assert_eq!(exit_code("fn main() { return 256; }"), 1);
+        assert_eq!(exit_code("fn main() { return -1; }"), 1);
+        assert_eq!(exit_code("fn main() { return 2 ^ 100; }"), 1);
+        assert_eq!(exit_code("fn main() { return \"3\"; }"), 0);
+        assert_eq!(exit_code("fn f() { return 5; } f();"), 0);
+    }
+
+    #[test]
+    fn builtins_use_eq_and_ord() {
+        assert_eq!(
+            eval(
+                r#"struct P { x }
+                struct Q { x }
+                impl Ord for P {
+                    fn cmp(a, b) { return b.x - a.x; }
+                }
+                impl Eq for Q {
+                    fn eq(a, b) { return true; }
+                }
+                fn main() {
+                    let ps = [P(1), P(3), P(2)];
+                    sort(ps);
+                    return [
+                        [p.x for p in ps],
+                        min(P(1), P(3)).x,
+                        max(P(1), P(3)).x,
+                        [P(1)] < [P(3)],
+                        [Q(1)] == [Q(3)],
+                        (1, Q(1)) == (1, Q(2)),
+                        {1: Q(1)} == {1: Q(2)},
+                        [P(1)] == [P(3)]
+                    ];
+                }"#
+            ),
+            eval("fn main() { return [[3, 2, 1], 3, 1, false, true, true, true, false]; }")
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot insert a List into itself")]
+    fn push_list_into_itself() {
+        eval("fn main() { let l = [1]; push(l, l); }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot insert a Map into itself")]
+    fn insert_map_into_itself() {
+        eval("fn main() { let m = {1: 1}; let l = [m]; set(m, 2, (0, l)); }");
+    }
+
+    #[test]
+    fn extend_list_with_itself() {
+        assert_eq!(
+            eval("fn main() { let l = [1, [2]]; extend(l, l); return l; }"),
+            eval("fn main() { return [1, [2], 1, [2]]; }")
+        );
+    }
+
+    #[test]
+    fn wide_int_literals() {
+        conforms(&[
+            ("9223372036854775808", "9223372036854775808"),
+            ("-9223372036854775808", "-9223372036854775808"),
+            ("0x1_0000_0000_0000_0000", "18446744073709551616"),
+            ("18446744073709551616 - 18446744073709551615", "1"),
+            ("0 ^ 5000000000", "0"),
+            ("1 ^ 5000000000", "1"),
+            ("(-1) ^ 5000000001", "-1"),
+            ("(-1) ^ 5000000000", "1"),
+        ]);
+        assert_eq!(
+            eval("fn main() { return -9223372036854775808; }"),
+            Value::IntValue(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn deprecated_warns_per_call_site() {
+        let code = r#"@deprecated("use g")
+            fn f() { return 1; }
+            fn main() {
+                let n = 0;
+                while (n < 3) { n += f(); }
+                return f();
+            }"#;
+        let tokens = Lexer::new(code.to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new(ast);
+        interpreter.eval();
+        let mut lines: Vec<i64> = interpreter
+            .deprecated
+            .iter()
+            .map(|(_, _span)| _span.from.line)
+            .collect();
+        lines.sort();
+        assert_eq!(lines, vec![5, 6]);
+    }
+
+    #[test]
+    fn pipe_binds_looser_than_comparisons() {
+        assert_eq!(
+            eval(
+                r#"fn negate(b) { return !b; }
+                fn main() {
+                    let xs = [1, 2, 3];
+                    return [1 < 2 |> negate(), 1 == 2 |> negate, (xs |> len()) > 2, 1 + 2 |> str()];
+                }"#
+            ),
+            eval(r#"fn main() { return [false, true, true, "3"]; }"#)
+        );
+    }
+
+    #[test]
+    fn formatting_keeps_literals() {
+        let code = r#"fn main() { return repr([1e3, 2E2, 1.5, 0xff, 1_000, r"C:\p", "a\tb"]); }"#;
+        let tokens = Lexer::new(code.to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let formatted = crate::formatter::Formatter::new(4).format(ast);
+        assert!(formatted.contains(r#"[1e3, 2E2, 1.5, 0xff, 1_000, r"C:\p", "a\tb"]"#));
+        assert_eq!(eval(&formatted), eval(code));
+        assert_eq!(
+            eval(&formatted),
+            Value::StringValue(String::from(
+                r#"[1000.0, 200.0, 1.5, 255, 1000, "C:\\p", "a\tb"]"#
+            ))
+        );
+    }
+
+    #[test]
+    fn set_and_pop_change_in_place() {
+        assert_eq!(
+            eval(
+                r#"fn main() {
+                    let a = [1, 2, 3];
+                    let b = set(a, 0, 9);
+                    let c = pop(a, 1);
+                    let m = {"k": 1, "j": 2};
+                    pop(m, "k");
+                    set(m, "l", 3);
+                    return [a, b, c, m];
+                }"#
+            ),
+            eval(r#"fn main() { return [[9, 3], [9, 3], [9, 3], {"j": 2, "l": 3}]; }"#)
+        );
+    }
+
+    #[test]
+    fn str_converts_tuples_sets_and_maps() {
+        assert_eq!(
+            eval(
+                r#"fn main() {
+                    return [str((1, "a")), str(#{2, 1}), str({"k": (1, 2.0)})];
+                }"#
+            ),
+            eval(r##"fn main() { return ["(1, \"a\")", "#{1, 2}", "{\"k\": (1, 2.0)}"]; }"##)
+        );
     }
 }
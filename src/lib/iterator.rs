@@ -0,0 +1,195 @@
+use std::{cell::RefCell, collections::HashSet, fmt::Debug, rc::Rc};
+
+use crate::{
+    interpreter::Interpreter,
+    nodes::{Node, WhileLoopNode},
+    scope::ScopePtr,
+    values::Value,
+};
+
+pub type IteratorPtr = Rc<RefCell<Iter>>;
+
+// A lazy sequence of values, shared by every Value that refers to it
+pub struct Iter {
+    pub source: Source,
+    // Value pulled by has_next, handed out by the following next
+    pub peeked: Option<Option<Value>>,
+}
+
+pub enum Source {
+    Values(std::vec::IntoIter<Value>),
+    Range { next: i64, end: i64, step: i64 },
+    Generator(Generator),
+    Take(IteratorPtr, usize),
+    Skip(IteratorPtr, usize),
+}
+
+// Suspended generator function, resumed statement by statement
+pub struct Generator {
+    pub frames: Vec<Frame>,
+}
+
+pub enum Frame {
+    Block {
+        stmts: Vec<Node>,
+        idx: usize,
+        scope: ScopePtr,
+    },
+    While {
+        node: WhileLoopNode,
+        scope: ScopePtr,
+        // Set until the first iteration of a do-while
+        skip_check: bool,
+    },
+}
+
+impl Iter {
+    pub fn new(source: Source) -> IteratorPtr {
+        Rc::new(RefCell::new(Iter {
+            source,
+            peeked: None,
+        }))
+    }
+
+    pub fn generator(stmts: Vec<Node>, scope: ScopePtr) -> IteratorPtr {
+        Iter::new(Source::Generator(Generator {
+            frames: vec![Frame::Block {
+                stmts,
+                idx: 0,
+                scope,
+            }],
+        }))
+    }
+}
+
+impl Debug for Iter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Iterator")
+    }
+}
+
+// Iterator over the elements of a collection, or the iterator itself
+pub fn to_iterator(value: Value) -> IteratorPtr {
+    match value {
+        Value::IteratorValue(_it) => _it,
+        // Lists are iterated as they were when the iterator was made
+        Value::ListValue(_l) => Iter::new(Source::Values(_l.borrow().clone().into_iter())),
+        Value::TupleValue(_t) => Iter::new(Source::Values(_t.into_iter())),
+        Value::StringValue(_s) => Iter::new(Source::Values(
+            _s.chars()
+                .map(|_c| Value::StringValue(_c.to_string()))
+                .collect::<Vec<Value>>()
+                .into_iter(),
+        )),
+        Value::MapValue(_m) => Iter::new(Source::Values(
+            _m.borrow()
+                .keys()
+                .map(Value::deep_copy)
+                .collect::<Vec<Value>>()
+                .into_iter(),
+        )),
+        Value::SetValue(_s) => Iter::new(Source::Values(
+            _s.iter()
+                .map(Value::deep_copy)
+                .collect::<Vec<Value>>()
+                .into_iter(),
+        )),
+        _ => panic!("Cannot iterate over {}", value.type_name()),
+    }
+}
+
+fn to_count(value: Option<&Value>) -> usize {
+    match value {
+        Some(Value::IntValue(_i)) if *_i >= 0 => *_i as usize,
+        _ => panic!("Expected a non-negative Int count as second argument"),
+    }
+}
+
+pub fn ash_iter(_: &mut Interpreter, args: Vec<Value>) -> Value {
+    if args.len() != 1 {
+        panic!("Invalid arguments")
+    }
+    Value::IteratorValue(to_iterator(args.into_iter().next().unwrap()))
+}
+
+pub fn ash_range(_: &mut Interpreter, args: Vec<Value>) -> Value {
+    let ints = args
+        .iter()
+        .map(|_v| match _v {
+            Value::IntValue(_i) => *_i,
+            _ => panic!("range expects Int arguments, found {}", _v.type_name()),
+        })
+        .collect::<Vec<i64>>();
+    let (next, end, step) = match ints[..] {
+        [end] => (0, end, 1),
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step),
+        _ => panic!("Invalid arguments"),
+    };
+    if step == 0 {
+        panic!("range step can't be zero")
+    }
+    Value::IteratorValue(Iter::new(Source::Range { next, end, step }))
+}
+
+// next(it) or next(it, default) once the iterator is exhausted
+pub fn ash_next(interpreter: &mut Interpreter, args: Vec<Value>) -> Value {
+    let it = match args.first() {
+        Some(Value::IteratorValue(_it)) => _it.clone(),
+        _ => panic!("next expects an Iterator as first argument"),
+    };
+    match interpreter.iter_next(&it) {
+        Some(_v) => _v,
+        None => match args.get(1) {
+            Some(_default) => _default.clone(),
+            None => panic!("Iterator is exhausted"),
+        },
+    }
+}
+
+pub fn ash_has_next(interpreter: &mut Interpreter, args: Vec<Value>) -> Value {
+    let it = match args.first() {
+        Some(Value::IteratorValue(_it)) => _it.clone(),
+        _ => panic!("has_next expects an Iterator as first argument"),
+    };
+    let next = interpreter.iter_next(&it);
+    let has_next = next.is_some();
+    it.borrow_mut().peeked = Some(next);
+    Value::BooleanValue(has_next)
+}
+
+pub fn ash_take(_: &mut Interpreter, args: Vec<Value>) -> Value {
+    let count = to_count(args.get(1));
+    let inner = to_iterator(args.into_iter().next().expect("Invalid arguments"));
+    Value::IteratorValue(Iter::new(Source::Take(inner, count)))
+}
+
+pub fn ash_skip(_: &mut Interpreter, args: Vec<Value>) -> Value {
+    let count = to_count(args.get(1));
+    let inner = to_iterator(args.into_iter().next().expect("Invalid arguments"));
+    Value::IteratorValue(Iter::new(Source::Skip(inner, count)))
+}
+
+pub fn ash_collect(interpreter: &mut Interpreter, args: Vec<Value>) -> Value {
+    if args.len() != 1 {
+        panic!("Invalid arguments")
+    }
+    let it = to_iterator(args.into_iter().next().unwrap());
+    let mut res = vec![];
+    while let Some(_v) = interpreter.iter_next(&it) {
+        res.push(_v);
+    }
+    Value::list(res)
+}
+
+pub fn ash_to_set(interpreter: &mut Interpreter, args: Vec<Value>) -> Value {
+    if args.len() != 1 {
+        panic!("Invalid arguments")
+    }
+    let it = to_iterator(args.into_iter().next().unwrap());
+    let mut res = HashSet::new();
+    while let Some(_v) = interpreter.iter_next(&it) {
+        res.insert(_v.deep_copy());
+    }
+    Value::SetValue(res)
+}
@@ -1,3 +1,5 @@
+use num_bigint::BigInt;
+
 use crate::{
     tokens::{PosRange, Position},
     utils::is_keyword,
@@ -8,9 +10,96 @@ use super::tokens::Token;
 type LexResult = Result<Vec<Token>, String>;
 type TokenResult = Result<Token, String>;
 
+// Integer literals wider than an i64 become BigInts
+fn int_token(digits: &str, radix: u32, raw: String, pos: PosRange) -> Token {
+    match i64::from_str_radix(digits, radix) {
+        Ok(_i) => Token::Int(_i, raw, pos),
+        Err(_) => Token::BigInt(
+            BigInt::parse_bytes(digits.as_bytes(), radix).expect("Digits were checked"),
+            raw,
+            pos,
+        ),
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn unescape(text: &str, from: &Position) -> Result<String, String> {
+    let invalid = |msg: &str| {
+        format!(
+            "Invalid Token [{}:{}]: {} in string",
+            from.line, from.column, msg
+        )
+    };
+    let mut chars = text.chars().peekable();
+    let mut res = String::from("");
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some('0') => res.push('\0'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(code) if hex.len() == 2 && code <= 0x7F => res.push(code as char),
+                    _ => return Err(invalid("Expected '\\x00'-'\\x7F'")),
+                }
+            }
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err(invalid("Expected '{' after '\\u'"));
+                }
+                let mut hex = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => hex.push(c),
+                        None => return Err(invalid("Expected '}' to close '\\u{'")),
+                    }
+                }
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(code) if hex.len() <= 6 => res.push(code),
+                    _ => return Err(invalid(&format!("Invalid unicode escape '\\u{{{}}}'", hex))),
+                }
+            }
+            Some(other) => res.push(other),
+            None => return Err(invalid("Unterminated escape")),
+        }
+    }
+    Ok(res)
+}
+
+// Drops the first and last line breaks of a """ string and the indentation
+// shared by all of its non-blank lines, counted in chars
+fn strip_indent(text: &str) -> String {
+    let text = text.strip_prefix('\n').unwrap_or(text);
+    let text = match text.rfind('\n') {
+        Some(idx) if text[idx + 1..].trim().is_empty() => &text[..idx],
+        _ => text,
+    };
+    let indent = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().count() - line.trim_start().chars().count())
+        .min()
+        .unwrap_or(0);
+    text.lines()
+        .map(|line| line.chars().skip(indent).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 // Lexer
 pub struct Lexer {
-    code: String,
+    // Source as code points, so `pos` and `len` agree on non-ASCII input
+    code: Vec<char>,
     pos: usize,
     line: i64,
     col: i64,
@@ -19,7 +108,7 @@ pub struct Lexer {
 impl Lexer {
     pub fn new(code: String) -> Self {
         Lexer {
-            code,
+            code: code.chars().collect(),
             pos: 0,
             line: 1,
             col: 1,
@@ -27,7 +116,12 @@ impl Lexer {
     }
 
     fn curr(&self) -> char {
-        self.code.chars().nth(self.pos).unwrap()
+        self.code[self.pos]
+    }
+
+    // Source text from `start` up to the current position
+    fn spelling(&self, start: usize) -> String {
+        self.code[start..self.pos].iter().collect()
     }
 
     fn get_pos(&self) -> Position {
@@ -86,38 +180,116 @@ impl Lexer {
     }
     fn lookahead(&self) -> char {
         if (self.pos + 1) < self.code.len() {
-            self.code.chars().nth(self.pos + 1).unwrap()
+            self.code[self.pos + 1]
+        } else {
+            ' '
+        }
+    }
+
+    fn peek(&self, offset: usize) -> char {
+        if (self.pos + offset) < self.code.len() {
+            self.code[self.pos + offset]
         } else {
             ' '
         }
     }
 
+    // Collects digits of the given radix, allowing '_' between two digits
+    fn parse_digits(&mut self, num: &mut String, radix: u32) -> Result<(), String> {
+        while self.pos < self.code.len() {
+            let c = self.curr();
+            if c.is_digit(radix) {
+                num.push(c);
+            } else if c == '_' {
+                let prev_is_digit = num.chars().last().is_some_and(|p| p.is_digit(radix));
+                if !prev_is_digit || !self.lookahead().is_digit(radix) {
+                    let pos = self.get_pos();
+                    return Err(format!(
+                        "Invalid Token [{}:{}]: '_' must separate two digits",
+                        pos.line, pos.column
+                    ));
+                }
+            } else {
+                break;
+            }
+            self.next()?;
+        }
+        Ok(())
+    }
+
     fn parse_number(&mut self) -> TokenResult {
         let mut num = String::from("");
         let from = self.get_pos();
-
-        let mut dots = 0;
-        while self.pos < self.code.len() && "0123456789.".contains(self.curr()) {
-            if self.curr() == '.' {
-                dots += 1;
+        let start = self.pos;
+
+        let radix = match (self.curr(), self.lookahead()) {
+            ('0', 'x' | 'X') => 16,
+            ('0', 'o' | 'O') => 8,
+            ('0', 'b' | 'B') => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            // Eat '0' and the radix prefix
+            self.next()?;
+            self.next()?;
+            self.parse_digits(&mut num, radix)?;
+            let to = self.get_pos();
+            if num.is_empty() {
+                return Err(format!(
+                    "Invalid Token [{}:{}]: Expected digits after radix prefix",
+                    from.line, from.column
+                ));
             }
-            if dots < 2 {
+            return Ok(int_token(
+                &num,
+                radix,
+                self.spelling(start),
+                PosRange::new(from, Some(to)),
+            ));
+        }
+
+        let mut is_double = false;
+        self.parse_digits(&mut num, 10)?;
+        // `1.len()` is a method call on an Int and `1..5` a range, not doubles
+        let after_dot = self.lookahead();
+        if self.pos < self.code.len()
+            && self.curr() == '.'
+            && !(after_dot.is_alphabetic() || "_.".contains(after_dot))
+        {
+            is_double = true;
+            num.push('.');
+            self.next()?;
+            self.parse_digits(&mut num, 10)?;
+        }
+
+        // Exponent, only when followed by digits so `2e` stays an error in the parser
+        let exp_digit = match self.lookahead() {
+            '+' | '-' => self.peek(2),
+            c => c,
+        };
+        if self.pos < self.code.len() && "eE".contains(self.curr()) && exp_digit.is_ascii_digit() {
+            is_double = true;
+            num.push('e');
+            self.next()?;
+            if "+-".contains(self.curr()) {
                 num.push(self.curr());
                 self.next()?;
-            } else {
-                break;
             }
+            self.parse_digits(&mut num, 10)?;
         }
         let to = self.get_pos();
 
-        if dots != 0 {
+        if is_double {
             Ok(Token::Double(
                 num.parse::<f64>().unwrap(),
+                self.spelling(start),
                 PosRange::new(from, Some(to)),
             ))
         } else {
-            Ok(Token::Int(
-                num.parse::<i64>().unwrap(),
+            Ok(int_token(
+                &num,
+                10,
+                self.spelling(start),
                 PosRange::new(from, Some(to)),
             ))
         }
@@ -126,10 +298,7 @@ impl Lexer {
     fn parse_identifier(&mut self) -> TokenResult {
         let mut id = String::from("");
         let from = self.get_pos();
-        while self.pos < self.code.len()
-            && (('a'..='z').contains(&self.curr().to_ascii_lowercase())
-                || ('0'..='9').contains(&self.curr().to_ascii_lowercase()))
-        {
+        while self.pos < self.code.len() && is_identifier_char(self.curr()) {
             id.push(self.curr());
             self.next()?;
         }
@@ -144,41 +313,109 @@ impl Lexer {
             Ok(Token::Identifier(id, PosRange::new(from, Some(to))))
         }
     }
+    // Reads raw source up to the closing delimiter, keeping escapes intact
+    fn read_until(&mut self, delimiter: &str, raw: bool) -> Result<String, String> {
+        let delimiter: Vec<char> = delimiter.chars().collect();
+        let mut text = String::from("");
+        while self.pos < self.code.len()
+            && (0..delimiter.len()).any(|i| self.peek(i) != delimiter[i])
+        {
+            if !raw && self.curr() == '\\' {
+                text.push(self.curr());
+                self.next()?;
+                if self.pos >= self.code.len() {
+                    return Err("Reached EOF".to_string());
+                }
+            }
+            if self.curr() == '\n' {
+                self.line += 1;
+                self.col = 0;
+            }
+            text.push(self.curr());
+            self.next()?;
+        }
+        // Eat the closing delimiter
+        for _ in 0..delimiter.len() {
+            self.next()?;
+        }
+        Ok(text)
+    }
+
     fn parse_string(&mut self) -> TokenResult {
-        let mut id = String::from("");
         let from = self.get_pos();
+        let start = self.pos;
 
         // Eat '"'
         self.next()?;
 
-        let mut escape = false;
-        while self.pos < self.code.len() && self.curr() != '"' {
-            if self.curr() == '\\' {
-                escape = true;
-                self.next()?;
-            }
+        let text = if self.curr() == '"' && self.lookahead() == '"' {
+            // Eat the rest of '"""'
+            self.next()?;
+            self.next()?;
+            strip_indent(&self.read_until("\"\"\"", false)?)
+        } else {
+            self.read_until("\"", false)?
+        };
+        let to = self.get_pos();
 
-            if escape {
-                match self.curr() {
-                    'n' => id.push('\n'),
-                    't' => id.push('\t'),
-                    'r' => id.push('\r'),
-                    _ => id.push(self.curr()),
-                }
-                escape = false
-            } else {
-                id.push(self.curr());
-            }
+        Ok(Token::String(
+            unescape(&text, &from)?,
+            self.spelling(start),
+            PosRange::new(from, Some(to)),
+        ))
+    }
 
-            self.next()?;
-        }
+    // Parses r"..." where backslashes are taken literally
+    fn parse_raw_string(&mut self) -> TokenResult {
+        let from = self.get_pos();
+        let start = self.pos;
 
-        // Eat '"'
+        // Eat 'r"'
+        self.next()?;
         self.next()?;
 
+        let text = self.read_until("\"", true)?;
         let to = self.get_pos();
 
-        Ok(Token::String(id, PosRange::new(from, Some(to))))
+        Ok(Token::String(
+            text,
+            self.spelling(start),
+            PosRange::new(from, Some(to)),
+        ))
+    }
+
+    // Parses .&. .|. .^. and their compound assignments
+    fn parse_bitwise_operator(&mut self) -> TokenResult {
+        let from = self.get_pos();
+
+        // Eat '.'
+        self.next()?;
+        let op = self.curr();
+        self.next()?;
+        if self.pos >= self.code.len() || self.curr() != '.' {
+            return Err(format!(
+                "Invalid Token [{}:{}]: Expected '.{}.'",
+                from.line, from.column, op
+            ));
+        }
+        // Eat '.'
+        self.next()?;
+
+        let assign = self.pos < self.code.len() && self.curr() == '=';
+        if assign {
+            self.next()?;
+        }
+        let to = self.get_pos();
+        let pos = PosRange::new(from, Some(to));
+
+        Ok(match (op, assign) {
+            ('&', false) => Token::BitAnd(pos),
+            ('|', false) => Token::BitOr(pos),
+            ('^', false) => Token::BitXor(pos),
+            ('&', true) => Token::BitAndEq(pos),
+            ('|', true) => Token::BitOrEq(pos),
+            _ => Token::BitXorEq(pos),
+        })
     }
 
     fn parse_comment(&mut self) -> TokenResult {
@@ -215,8 +452,29 @@ impl Lexer {
                     self.next()?
                 }
                 '"' => tokens.push(self.parse_string()?),
+                'r' if self.curr() == 'r' && self.lookahead() == '"' => {
+                    tokens.push(self.parse_raw_string()?)
+                }
+                '.' if "&|^".contains(self.lookahead()) => {
+                    tokens.push(self.parse_bitwise_operator()?)
+                }
+                '.' if self.lookahead() == '.' => {
+                    let from = self.get_pos();
+                    self.next()?;
+                    let to = self.get_pos();
+                    tokens.push(Token::DotDot(PosRange::new(from, Some(to))));
+                    self.next()?
+                }
+                '.' if !self.lookahead().is_ascii_digit() => {
+                    tokens.push(Token::Dot(PosRange::new(self.get_pos(), None)));
+                    self.next()?
+                }
+                '@' => {
+                    tokens.push(Token::At(PosRange::new(self.get_pos(), None)));
+                    self.next()?
+                }
                 '0'..='9' | '.' => tokens.push(self.parse_number()?),
-                _ if c.is_ascii_alphabetic() => tokens.push(self.parse_identifier()?),
+                _ if c.is_alphabetic() || c == '_' => tokens.push(self.parse_identifier()?),
                 '+' => {
                     tokens.push(self.add_double_char_token(
                         &mut Token::Plus(PosRange::empty()),
@@ -224,6 +482,13 @@ impl Lexer {
                         ['+', '='],
                     )?);
                 }
+                '-' if self.lookahead() == '>' => {
+                    let from = self.get_pos();
+                    self.next()?;
+                    let to = self.get_pos();
+                    tokens.push(Token::Arrow(PosRange::new(from, Some(to))));
+                    self.next()?
+                }
                 '-' => {
                     tokens.push(self.add_double_char_token(
                         &mut Token::Minus(PosRange::empty()),
@@ -303,25 +568,53 @@ impl Lexer {
                     )?);
                 }
                 '<' => {
-                    tokens.push(self.add_double_char_token(
-                        &mut Token::LessThan(PosRange::empty()),
-                        &mut Token::LessThanEq(PosRange::empty()),
-                        ['<', '='],
-                    )?);
+                    if self.lookahead() == '=' {
+                        tokens.push(self.add_double_char_token(
+                            &mut Token::LessThan(PosRange::empty()),
+                            &mut Token::LessThanEq(PosRange::empty()),
+                            ['<', '='],
+                        )?);
+                    } else {
+                        tokens.push(self.add_triple_char_token(
+                            &mut Token::LessThan(PosRange::empty()),
+                            &mut Token::ShiftLeft(PosRange::empty()),
+                            &mut Token::ShiftLeftEq(PosRange::empty()),
+                            ['<', '<', '='],
+                        )?);
+                    }
                 }
                 '>' => {
-                    tokens.push(self.add_double_char_token(
-                        &mut Token::GreaterThan(PosRange::empty()),
-                        &mut Token::GreaterThanEq(PosRange::empty()),
-                        ['>', '='],
-                    )?);
+                    if self.lookahead() == '=' {
+                        tokens.push(self.add_double_char_token(
+                            &mut Token::GreaterThan(PosRange::empty()),
+                            &mut Token::GreaterThanEq(PosRange::empty()),
+                            ['>', '='],
+                        )?);
+                    } else {
+                        tokens.push(self.add_triple_char_token(
+                            &mut Token::GreaterThan(PosRange::empty()),
+                            &mut Token::ShiftRight(PosRange::empty()),
+                            &mut Token::ShiftRightEq(PosRange::empty()),
+                            ['>', '>', '='],
+                        )?);
+                    }
                 }
                 '&' => {
                     tokens.push(Token::And(PosRange::new(self.get_pos(), None)));
                     self.next()?
                 }
                 '|' => {
-                    tokens.push(Token::Or(PosRange::new(self.get_pos(), None)));
+                    tokens.push(self.add_double_char_token(
+                        &mut Token::Or(PosRange::empty()),
+                        &mut Token::Pipe(PosRange::empty()),
+                        ['|', '>'],
+                    )?);
+                }
+                '#' if self.lookahead() == '{' => {
+                    let from = self.get_pos();
+                    self.next()?;
+                    let to = self.get_pos();
+                    tokens.push(Token::HashLBrace(PosRange::new(from, Some(to))));
                     self.next()?
                 }
                 '(' => {
@@ -515,6 +808,92 @@ mod tests {
         )
     }
 
+    #[test]
+    fn unicode_identifiers() {
+        let mut lexer = Lexer::new("snake_case ñandú _x1".to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier(
+                    "snake_case".to_string(),
+                    PosRange::new(Position::from(1, 1), Position::to(1, 11))
+                ),
+                Token::Identifier(
+                    "ñandú".to_string(),
+                    PosRange::new(Position::from(1, 12), Position::to(1, 17))
+                ),
+                Token::Identifier(
+                    "_x1".to_string(),
+                    PosRange::new(Position::from(1, 18), Position::to(1, 21))
+                ),
+                Token::EOF(PosRange::new(Position::from(1, 21), None)),
+            ]
+        )
+    }
+
+    #[test]
+    fn number_literals() {
+        let mut lexer = Lexer::new("0xFF 0b1010 0o17 1_000_000 1e-9 2.5E3".to_string());
+        let values: Vec<String> = lexer
+            .tokenize()
+            .unwrap()
+            .iter()
+            .map(|tkn| match tkn {
+                Token::Int(_i, _, _) => format!("Int({})", _i),
+                Token::Double(_d, _, _) => format!("Double({})", _d),
+                _ => tkn.get_name(),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                "Int(255)",
+                "Int(10)",
+                "Int(15)",
+                "Int(1000000)",
+                "Double(0.000000001)",
+                "Double(2500)",
+                "EOF"
+            ]
+        );
+        assert!(Lexer::new("1__0".to_string()).tokenize().is_err());
+    }
+
+    #[test]
+    fn string_literals() {
+        let code = r#"
+            "\x41\u{1F600}\"" r"C:\path" """
+                first
+                  second
+                """
+        "#;
+        let strings: Vec<String> = Lexer::new(code.to_string())
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .filter_map(|tkn| match tkn {
+                Token::String(_s, _, _) => Some(_s),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(strings, vec!["A\u{1F600}\"", "C:\\path", "first\n  second"]);
+
+        assert_eq!(
+            Lexer::new(r#"let s = "abc\"#.to_string()).tokenize(),
+            Err("Reached EOF".to_string())
+        );
+        assert!(Lexer::new(r#"println("\u{41");"#.to_string())
+            .tokenize()
+            .unwrap_err()
+            .contains("Expected '}' to close '\\u{'"));
+        let ideographic = "\"\"\"\n  a\n\u{3000}\u{3000}\u{3000}b\n\"\"\"";
+        match &Lexer::new(ideographic.to_string()).tokenize().unwrap()[0] {
+            Token::String(_s, _, _) => assert_eq!(_s, "a\n\u{3000}b"),
+            _ => panic!("Expected String"),
+        }
+    }
+
     #[test]
     fn if_elif_else() {
         let mut lexer = Lexer::new("if elif else".to_string());
@@ -566,9 +945,33 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::Int(1, PosRange::new(Position::from(1, 1), Position::to(1, 2))),
+                Token::Int(
+                    1,
+                    String::from("1"),
+                    PosRange::new(Position::from(1, 1), Position::to(1, 2))
+                ),
                 Token::EOF(PosRange::new(Position::from(1, 2), None))
             ]
         )
     }
+
+    #[test]
+    fn bitwise() {
+        let mut lexer = Lexer::new(".&. .|. .^. << >> ~ .&.= <<=".to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BitAnd(PosRange::new(Position::from(1, 1), Position::to(1, 4))),
+                Token::BitOr(PosRange::new(Position::from(1, 5), Position::to(1, 8))),
+                Token::BitXor(PosRange::new(Position::from(1, 9), Position::to(1, 12))),
+                Token::ShiftLeft(PosRange::new(Position::from(1, 13), Position::to(1, 14))),
+                Token::ShiftRight(PosRange::new(Position::from(1, 16), Position::to(1, 17))),
+                Token::Tilde(PosRange::new(Position::from(1, 19), None)),
+                Token::BitAndEq(PosRange::new(Position::from(1, 21), Position::to(1, 25))),
+                Token::ShiftLeftEq(PosRange::new(Position::from(1, 26), Position::to(1, 28))),
+                Token::EOF(PosRange::new(Position::from(1, 29), None)),
+            ]
+        )
+    }
 }
@@ -1,11 +1,14 @@
 pub mod built_in;
+pub mod checker;
 pub mod formatter;
 pub mod interpreter;
+pub mod iterator;
 pub mod lexer;
 pub mod nodes;
 pub mod parser;
 pub mod scope;
 pub mod tokens;
+pub mod types;
 pub mod utils;
 pub mod values;
 
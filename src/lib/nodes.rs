@@ -1,23 +1,30 @@
 #![allow(clippy::new_ret_no_self)]
+use indexmap::IndexMap;
+use num_bigint::BigInt;
 use ordered_float::OrderedFloat;
 
-use crate::values::Value;
+use crate::{tokens::PosRange, types::Memo, values::unordered_hash};
 use std::{
     cell::RefCell,
-    collections::HashMap,
     fmt::{Debug, Display},
+    rc::Rc,
 };
 
 // Node
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Node {
     Int(IntNode),
+    BigInt(BigIntNode),
     Double(DoubleNode),
     Boolean(BooleanNode),
     String(StringNode),
     Comment(CommentNode),
     List(ListNode),
     Map(MapNode),
+    Set(SetNode),
+    Tuple(TupleNode),
+    ListComprehension(ListComprehensionNode),
+    MapComprehension(MapComprehensionNode),
     Identifier(IdentifierNode),
     UnaryNumber(UnaryNumberNode),
     UnaryBoolean(UnaryBooleanNode),
@@ -26,27 +33,47 @@ pub enum Node {
     Assignment(AssignmentNode),
     MultiDeclaration(MultiDeclarationNode),
     Declaration(DeclarationNode),
+    Destructure(DestructureNode),
     BlockStatement(BlockStatementNode),
     FunctionCall(FunctionCallNode),
+    Pipe(PipeNode),
+    MethodCall(MethodCallNode),
+    NamedArgument(NamedArgumentNode),
+    Spread(SpreadNode),
     FunctionDeclaration(FunctionDeclarationNode),
+    FunctionSignature(FunctionSignatureNode),
+    StructDeclaration(StructDeclarationNode),
+    TraitDeclaration(TraitDeclarationNode),
+    Impl(ImplNode),
+    FieldAccess(FieldAccessNode),
     WhileLoop(WhileLoopNode),
     IfStatement(IfStatementNode),
     ElifStatement(ElifStatementNode),
     Return(ReturnNode),
-    Break,
-    Continue,
+    Yield(YieldNode),
+    Break(Option<String>),
+    Continue(Option<String>),
 }
 
-// IntNode
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+// IntNode, `raw` is the literal as written
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct IntNode {
     pub value: i64,
+    pub raw: String,
+}
+
+// BigIntNode, an Int literal wider than an i64
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigIntNode {
+    pub value: BigInt,
+    pub raw: String,
 }
 
 // DoubleNode
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct DoubleNode {
     pub value: OrderedFloat<f64>,
+    pub raw: String,
 }
 // BooleanNode
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -57,6 +84,7 @@ pub struct BooleanNode {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct StringNode {
     pub value: String,
+    pub raw: String,
 }
 
 // CommentNode
@@ -70,10 +98,28 @@ pub struct ListNode {
     pub elements: Vec<Node>,
 }
 
-// MapNode
+// TupleNode: (), (a,) or (a, b)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TupleNode {
+    pub elements: Vec<Node>,
+}
+
+impl TupleNode {
+    pub fn new(elements: Vec<Node>) -> Node {
+        Node::Tuple(TupleNode { elements })
+    }
+}
+
+// SetNode: #{a, b}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SetNode {
+    pub elements: Vec<Node>,
+}
+
+// MapNode: entries in source order
 #[derive(Debug, Clone, Eq)]
 pub struct MapNode {
-    pub elements: HashMap<Node, Node>,
+    pub elements: IndexMap<Node, Node>,
 }
 
 impl PartialEq for MapNode {
@@ -84,9 +130,7 @@ impl PartialEq for MapNode {
 
 impl std::hash::Hash for MapNode {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        for (k, v) in self.elements.iter() {
-            (k, v).hash(state);
-        }
+        unordered_hash(self.elements.iter()).hash(state)
     }
 }
 
@@ -101,12 +145,14 @@ pub struct IdentifierNode {
 pub enum UnaryArithmetic {
     Plus,
     Minus,
+    BitNot,
 }
 impl Display for UnaryArithmetic {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             UnaryArithmetic::Plus => write!(f, "+"),
             UnaryArithmetic::Minus => write!(f, "-"),
+            UnaryArithmetic::BitNot => write!(f, "~"),
         }
     }
 }
@@ -142,6 +188,9 @@ impl UnaryNumberNode {
     pub fn minus(value: Box<Node>) -> Node {
         UnaryNumberNode::new(value, UnaryArithmetic::Minus)
     }
+    pub fn bit_not(value: Box<Node>) -> Node {
+        UnaryNumberNode::new(value, UnaryArithmetic::BitNot)
+    }
 }
 // UnaryBooleanNode
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -170,6 +219,11 @@ pub enum Arithmetic {
     Modulus,     // %
     TildeDivide, // ~/
     PowerDivide, // ^/
+    BitAnd,      // .&.
+    BitOr,       // .|.
+    BitXor,      // .^.
+    ShiftLeft,   // <<
+    ShiftRight,  // >>
 }
 
 impl Display for Arithmetic {
@@ -183,6 +237,11 @@ impl Display for Arithmetic {
             Arithmetic::Modulus => write!(f, "%"),
             Arithmetic::TildeDivide => write!(f, "~/"),
             Arithmetic::PowerDivide => write!(f, "^/"),
+            Arithmetic::BitAnd => write!(f, ".&."),
+            Arithmetic::BitOr => write!(f, ".|."),
+            Arithmetic::BitXor => write!(f, ".^."),
+            Arithmetic::ShiftLeft => write!(f, "<<"),
+            Arithmetic::ShiftRight => write!(f, ">>"),
         }
     }
 }
@@ -228,6 +287,11 @@ pub enum Assignment {
     PowerEq,       // ^=
     TildeDivideEq, // ~/=
     PowerDivideEq, // ^/=
+    BitAndEq,      // .&.=
+    BitOrEq,       // .|.=
+    BitXorEq,      // .^.=
+    ShiftLeftEq,   // <<=
+    ShiftRightEq,  // >>=
 }
 
 impl Display for Assignment {
@@ -242,6 +306,11 @@ impl Display for Assignment {
             Assignment::PowerEq => write!(f, "^="),
             Assignment::TildeDivideEq => write!(f, "~/="),
             Assignment::PowerDivideEq => write!(f, "^/="),
+            Assignment::BitAndEq => write!(f, ".&.="),
+            Assignment::BitOrEq => write!(f, ".|.="),
+            Assignment::BitXorEq => write!(f, ".^.="),
+            Assignment::ShiftLeftEq => write!(f, "<<="),
+            Assignment::ShiftRightEq => write!(f, ">>="),
         }
     }
 }
@@ -282,6 +351,21 @@ impl BinaryOpNumberNode {
     pub fn modulus(left: Box<Node>, right: Box<Node>) -> Node {
         BinaryOpNumberNode::new(left, right, Arithmetic::Modulus)
     }
+    pub fn bit_and(left: Box<Node>, right: Box<Node>) -> Node {
+        BinaryOpNumberNode::new(left, right, Arithmetic::BitAnd)
+    }
+    pub fn bit_or(left: Box<Node>, right: Box<Node>) -> Node {
+        BinaryOpNumberNode::new(left, right, Arithmetic::BitOr)
+    }
+    pub fn bit_xor(left: Box<Node>, right: Box<Node>) -> Node {
+        BinaryOpNumberNode::new(left, right, Arithmetic::BitXor)
+    }
+    pub fn shift_left(left: Box<Node>, right: Box<Node>) -> Node {
+        BinaryOpNumberNode::new(left, right, Arithmetic::ShiftLeft)
+    }
+    pub fn shift_right(left: Box<Node>, right: Box<Node>) -> Node {
+        BinaryOpNumberNode::new(left, right, Arithmetic::ShiftRight)
+    }
 }
 // BinaryOpBooleanNode
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -343,11 +427,19 @@ impl AssignmentNode {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BlockStatementNode {
     pub value: Vec<Node>,
+    // Source range of each statement, empty for blocks built after parsing
+    pub spans: Vec<PosRange>,
 }
 
 impl BlockStatementNode {
     pub fn new(value: Vec<Node>) -> Node {
-        Node::BlockStatement(BlockStatementNode { value })
+        Node::BlockStatement(BlockStatementNode {
+            value,
+            spans: Vec::new(),
+        })
+    }
+    pub fn with_spans(value: Vec<Node>, spans: Vec<PosRange>) -> Node {
+        Node::BlockStatement(BlockStatementNode { value, spans })
     }
 }
 
@@ -364,13 +456,214 @@ impl FunctionCallNode {
     }
 }
 
+// PipeNode: value |> call(args), calls with value as the first argument
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipeNode {
+    pub value: Box<Node>,
+    pub call: FunctionCallNode,
+}
+
+impl PipeNode {
+    pub fn new(value: Box<Node>, call: FunctionCallNode) -> Node {
+        Node::Pipe(PipeNode { value, call })
+    }
+}
+
+// MethodCallNode: value.call(args), a type method or call(value, args)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MethodCallNode {
+    pub value: Box<Node>,
+    pub call: FunctionCallNode,
+}
+
+impl MethodCallNode {
+    pub fn new(value: Box<Node>, call: FunctionCallNode) -> Node {
+        Node::MethodCall(MethodCallNode { value, call })
+    }
+}
+
+// NamedArgumentNode: name: value
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NamedArgumentNode {
+    pub id: String,
+    pub value: Box<Node>,
+}
+
+impl NamedArgumentNode {
+    pub fn new(id: String, value: Box<Node>) -> Node {
+        Node::NamedArgument(NamedArgumentNode { id, value })
+    }
+}
+
+// SpreadNode: ..value
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpreadNode {
+    pub value: Box<Node>,
+}
+
+impl SpreadNode {
+    pub fn new(value: Box<Node>) -> Node {
+        Node::Spread(SpreadNode { value })
+    }
+}
+
+// Param: name, name = default or ..name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Param {
+    pub id: String,
+    pub default: Option<Box<Node>>,
+    pub rest: bool,
+    // Type of the argument, or of each argument collected by a rest param
+    pub ty: Option<Type>,
+}
+
+impl Param {
+    pub fn new(id: String) -> Self {
+        Param {
+            id,
+            default: None,
+            rest: false,
+            ty: None,
+        }
+    }
+    pub fn with_default(id: String, default: Box<Node>) -> Self {
+        Param {
+            id,
+            default: Some(default),
+            rest: false,
+            ty: None,
+        }
+    }
+    pub fn rest(id: String) -> Self {
+        Param {
+            id,
+            default: None,
+            rest: true,
+            ty: None,
+        }
+    }
+}
+
+// Type annotation: Int, List<Int>, Map<String, Double>, Point, ...
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Any,
+    Int,
+    Double,
+    Rational,
+    String,
+    Boolean,
+    None,
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Set(Box<Type>),
+    Tuple(Vec<Type>),
+    Iterator(Box<Type>),
+    Struct(String),
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Int => write!(f, "Int"),
+            Type::Double => write!(f, "Double"),
+            Type::Rational => write!(f, "Rational"),
+            Type::String => write!(f, "String"),
+            Type::Boolean => write!(f, "Boolean"),
+            Type::None => write!(f, "None"),
+            Type::List(_t) => write!(f, "List<{}>", _t),
+            Type::Map(_k, _v) => write!(f, "Map<{}, {}>", _k, _v),
+            Type::Set(_t) => write!(f, "Set<{}>", _t),
+            Type::Tuple(_ts) => write!(
+                f,
+                "Tuple<{}>",
+                _ts.iter()
+                    .map(|_t| _t.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Type::Iterator(_t) => write!(f, "Iterator<{}>", _t),
+            Type::Struct(_id) => write!(f, "{}", _id),
+        }
+    }
+}
+
+// `name` or `name: Type`
+fn typed(id: &str, ty: &Option<Type>) -> String {
+    match ty {
+        Some(_t) => format!("{}: {}", id, _t),
+        None => id.to_string(),
+    }
+}
+
+// ` -> Type` after the params of a function, if it declares one
+pub fn returns(ret: &Option<Type>) -> String {
+    match ret {
+        Some(_t) => format!(" -> {}", _t),
+        None => String::new(),
+    }
+}
+
+impl Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rest {
+            write!(f, "..")?;
+        }
+        write!(f, "{}", self.id)?;
+        if let Some(ty) = &self.ty {
+            write!(f, ": {}", ty)?;
+        }
+        if let Some(default) = &self.default {
+            write!(f, " = {}", default)?;
+        }
+        Ok(())
+    }
+}
+
+// Annotation: @trace or @memo(max: 100) before a function declaration
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Annotation {
+    pub id: String,
+    pub args: Vec<Node>,
+}
+impl Annotation {
+    pub fn new(id: &str, args: Vec<Node>) -> Self {
+        Annotation {
+            id: id.to_string(),
+            args,
+        }
+    }
+}
+impl Display for Annotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{}", self.id)?;
+        if !self.args.is_empty() {
+            write!(
+                f,
+                "({})",
+                self.args
+                    .iter()
+                    .map(|_a| format!("{}", _a))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
 // FunctionDeclarationNode
 #[derive(Debug, Clone, Eq)]
 pub struct FunctionDeclarationNode {
     pub id: String,
-    pub params: Vec<String>,
+    pub params: Vec<Param>,
     pub body: Box<Node>,
-    pub memo: Option<RefCell<HashMap<Vec<Value>, Value>>>,
+    pub annotations: Vec<Annotation>,
+    // Set by @memo, cfn is sugar for it. Copies of the declaration share it
+    pub memo: Option<Rc<RefCell<Memo>>>,
+    pub generator: bool,
+    pub ret: Option<Type>,
 }
 
 impl PartialEq for FunctionDeclarationNode {
@@ -378,7 +671,8 @@ impl PartialEq for FunctionDeclarationNode {
         self.id == other.id
             && self.params == other.params
             && self.body == other.body
-            && self.memo == other.memo
+            && self.annotations == other.annotations
+            && self.ret == other.ret
     }
 }
 
@@ -387,41 +681,116 @@ impl std::hash::Hash for FunctionDeclarationNode {
         self.id.hash(state);
         self.params.hash(state);
         self.body.hash(state);
-        core::mem::discriminant(&self.memo).hash(state);
+        self.annotations.hash(state);
+        self.ret.hash(state);
     }
 }
 
 impl FunctionDeclarationNode {
-    pub fn new_fn(id: String, params: Vec<String>, body: Box<Node>) -> Node {
+    pub fn new(
+        id: String,
+        params: Vec<Param>,
+        ret: Option<Type>,
+        body: Box<Node>,
+        annotations: Vec<Annotation>,
+    ) -> Node {
+        let memo = annotations.iter().find(|_a| _a.id == "memo").map(|_a| {
+            // @memo(100) or @memo(max: 100)
+            let max = match _a.args.first() {
+                Some(Node::NamedArgument(_na)) => Some(_na.value.as_ref()),
+                _arg => _arg,
+            };
+            Rc::new(RefCell::new(Memo::new(match max {
+                Some(Node::Int(_i)) => Some(_i.value as usize),
+                _ => None,
+            })))
+        });
         Node::FunctionDeclaration(FunctionDeclarationNode {
             id,
             params,
+            generator: body.contains_yield(),
             body,
-            memo: None,
+            annotations,
+            memo,
+            ret,
         })
     }
-    pub fn new_cfn(id: String, params: Vec<String>, body: Box<Node>) -> Node {
-        Node::FunctionDeclaration(FunctionDeclarationNode {
-            id,
-            params,
-            body,
-            memo: Some(RefCell::new(HashMap::new())),
-        })
+    pub fn annotation(&self, id: &str) -> Option<&Annotation> {
+        self.annotations.iter().find(|_a| _a.id == id)
+    }
+}
+
+// FunctionSignatureNode: fn name(params); a method a trait requires
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FunctionSignatureNode {
+    pub id: String,
+    pub params: Vec<Param>,
+    pub ret: Option<Type>,
+}
+impl FunctionSignatureNode {
+    pub fn new(id: String, params: Vec<Param>, ret: Option<Type>) -> Node {
+        Node::FunctionSignature(FunctionSignatureNode { id, params, ret })
+    }
+}
+
+// StructDeclarationNode: struct Point { x, y }
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StructDeclarationNode {
+    pub id: String,
+    pub fields: Vec<(String, Option<Type>)>,
+}
+impl StructDeclarationNode {
+    pub fn new(id: String, fields: Vec<(String, Option<Type>)>) -> Node {
+        Node::StructDeclaration(StructDeclarationNode { id, fields })
     }
-    pub fn contains_key(&self, args: &Vec<Value>) -> bool {
-        self.memo.as_ref().unwrap().borrow().contains_key(args)
+}
+
+// TraitDeclarationNode: trait Shape { fn area(self); fn name(self) { ... } }
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraitDeclarationNode {
+    pub id: String,
+    // FunctionSignatures to implement and FunctionDeclarations as defaults
+    pub methods: Vec<Node>,
+}
+impl TraitDeclarationNode {
+    pub fn new(id: String, methods: Vec<Node>) -> Node {
+        Node::TraitDeclaration(TraitDeclarationNode { id, methods })
+    }
+}
+
+// ImplNode: impl Point { ... } or impl Trait for Point { ... }
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImplNode {
+    pub trait_id: Option<String>,
+    pub type_id: String,
+    pub methods: Vec<Node>,
+}
+impl ImplNode {
+    pub fn new(trait_id: Option<String>, type_id: String, methods: Vec<Node>) -> Node {
+        Node::Impl(ImplNode {
+            trait_id,
+            type_id,
+            methods,
+        })
     }
-    pub fn set_cache(&self, args: Vec<Value>, res: Value) {
-        self.memo.as_ref().unwrap().borrow_mut().insert(args, res);
+
+    pub fn header(&self) -> String {
+        match &self.trait_id {
+            Some(_t) => format!("impl {} for {}", _t, self.type_id),
+            None => format!("impl {}", self.type_id),
+        }
     }
-    pub fn get_cache(&self, args: &Vec<Value>) -> Value {
-        self.memo
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .get(args)
-            .unwrap()
-            .clone()
+}
+
+// FieldAccessNode: value.field
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldAccessNode {
+    pub value: Box<Node>,
+    pub field: String,
+}
+impl FieldAccessNode {
+    pub fn new(value: Box<Node>, field: String) -> Node {
+        Node::FieldAccess(FieldAccessNode { value, field })
     }
 }
 
@@ -439,11 +808,205 @@ impl MultiDeclarationNode {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct DeclarationNode {
     pub id: String,
+    pub ty: Option<Type>,
     pub value: Box<Node>,
 }
 impl DeclarationNode {
-    pub fn new(id: String, value: Box<Node>) -> Node {
-        Node::Declaration(DeclarationNode { id, value })
+    pub fn new(id: String, ty: Option<Type>, value: Box<Node>) -> Node {
+        Node::Declaration(DeclarationNode { id, ty, value })
+    }
+
+    // The declared name along with its annotation
+    pub fn target(&self) -> String {
+        typed(&self.id, &self.ty)
+    }
+}
+
+// ListComprehensionNode: [element for x in xs if cond]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ListComprehensionNode {
+    pub element: Box<Node>,
+    pub clauses: Vec<ComprehensionClause>,
+}
+
+impl ListComprehensionNode {
+    pub fn new(element: Box<Node>, clauses: Vec<ComprehensionClause>) -> Node {
+        Node::ListComprehension(ListComprehensionNode { element, clauses })
+    }
+}
+
+// MapComprehensionNode: {key: value for k, v in m if cond}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MapComprehensionNode {
+    pub key: Box<Node>,
+    pub value: Box<Node>,
+    pub clauses: Vec<ComprehensionClause>,
+}
+
+impl MapComprehensionNode {
+    pub fn new(key: Box<Node>, value: Box<Node>, clauses: Vec<ComprehensionClause>) -> Node {
+        Node::MapComprehension(MapComprehensionNode {
+            key,
+            value,
+            clauses,
+        })
+    }
+}
+
+// Clauses run left to right, each `for` nested inside the previous one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ComprehensionClause {
+    // for a, b in iterable: several targets destructure each item, or each entry of a Map
+    For {
+        targets: Vec<Pattern>,
+        iterable: Box<Node>,
+    },
+    If(Box<Node>),
+}
+
+impl Display for ComprehensionClause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComprehensionClause::For { targets, iterable } => {
+                write!(f, "for {} in {}", join_patterns(targets), iterable)
+            }
+            ComprehensionClause::If(_cond) => write!(f, "if {}", _cond),
+        }
+    }
+}
+
+fn all_free_variables(nodes: &[Node], bound: &mut Vec<String>, free: &mut Vec<String>) {
+    nodes.iter().for_each(|_n| _n.free_variables(bound, free))
+}
+
+fn clause_free_variables(
+    clauses: &[ComprehensionClause],
+    bound: &mut Vec<String>,
+    free: &mut Vec<String>,
+) {
+    for clause in clauses {
+        match clause {
+            ComprehensionClause::For { targets, iterable } => {
+                iterable.free_variables(bound, free);
+                targets.iter().for_each(|_t| _t.names(bound));
+            }
+            ComprehensionClause::If(_cond) => _cond.free_variables(bound, free),
+        }
+    }
+}
+
+fn join_clauses(clauses: &[ComprehensionClause]) -> String {
+    clauses
+        .iter()
+        .map(|_c| format!("{}", _c))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// Pattern
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    Identifier(String),
+    List(ListPattern),
+    Map(MapPattern),
+    Tuple(Vec<Pattern>),
+}
+
+impl Pattern {
+    // Variables the pattern binds
+    pub fn names(&self, out: &mut Vec<String>) {
+        match self {
+            Pattern::Identifier(_id) => out.push(_id.clone()),
+            Pattern::List(_lp) => {
+                _lp.elements.iter().for_each(|_p| _p.names(out));
+                out.extend(_lp.rest.clone());
+            }
+            Pattern::Map(_mp) => _mp.entries.iter().for_each(|(_, _p)| _p.names(out)),
+            Pattern::Tuple(_ps) => _ps.iter().for_each(|_p| _p.names(out)),
+        }
+    }
+
+    // `let q, r = ...` declares a Tuple pattern without parentheses
+    pub fn declaration_target(&self) -> String {
+        match self {
+            Pattern::Tuple(_elements) => join_patterns(_elements),
+            _ => format!("{}", self),
+        }
+    }
+}
+
+fn join_patterns(patterns: &[Pattern]) -> String {
+    patterns
+        .iter()
+        .map(|_p| format!("{}", _p))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// ListPattern: [a, b, ..rest]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ListPattern {
+    pub elements: Vec<Pattern>,
+    pub rest: Option<String>,
+}
+
+// MapPattern: {name, age: years}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MapPattern {
+    pub entries: Vec<(String, Pattern)>,
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Identifier(_id) => write!(f, "{}", _id),
+            Pattern::List(_l) => {
+                let mut parts = _l
+                    .elements
+                    .iter()
+                    .map(|_p| format!("{}", _p))
+                    .collect::<Vec<String>>();
+                if let Some(rest) = &_l.rest {
+                    parts.push(format!("..{}", rest));
+                }
+                write!(f, "[{}]", parts.join(", "))
+            }
+            Pattern::Tuple(_elements) if _elements.len() == 1 => {
+                write!(f, "({},)", _elements[0])
+            }
+            Pattern::Tuple(_elements) => write!(f, "({})", join_patterns(_elements)),
+            Pattern::Map(_m) => {
+                write!(
+                    f,
+                    "{{{}}}",
+                    _m.entries
+                        .iter()
+                        .map(|(key, _p)| match _p {
+                            Pattern::Identifier(_id) if _id == key => key.to_string(),
+                            _ => format!("{}: {}", key, _p),
+                        })
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+        }
+    }
+}
+
+// DestructureNode
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DestructureNode {
+    pub pattern: Pattern,
+    pub value: Box<Node>,
+    pub declare: bool,
+}
+impl DestructureNode {
+    pub fn new(pattern: Pattern, value: Box<Node>, declare: bool) -> Node {
+        Node::Destructure(DestructureNode {
+            pattern,
+            value,
+            declare,
+        })
     }
 }
 
@@ -452,13 +1015,51 @@ impl DeclarationNode {
 pub struct WhileLoopNode {
     pub condition: Box<Node>,
     pub body: Box<Node>,
+    pub kind: LoopKind,
+    pub label: Option<String>,
 }
 impl WhileLoopNode {
-    pub fn new(condition: Box<Node>, body: Box<Node>) -> Node {
-        Node::WhileLoop(WhileLoopNode { condition, body })
+    pub fn new(
+        condition: Box<Node>,
+        body: Box<Node>,
+        kind: LoopKind,
+        label: Option<String>,
+    ) -> Node {
+        Node::WhileLoop(WhileLoopNode {
+            condition,
+            body,
+            kind,
+            label,
+        })
+    }
+
+    // Whether a break or continue with this label applies to the loop
+    pub fn is_target(&self, label: &Option<String>) -> bool {
+        label.is_none() || *label == self.label
+    }
+
+    // Header of the loop, everything but the body and a do-while condition
+    pub fn header(&self) -> String {
+        let label = match &self.label {
+            Some(_l) => format!("{}: ", _l),
+            None => String::new(),
+        };
+        match self.kind {
+            LoopKind::While => format!("{}while ({})", label, self.condition),
+            LoopKind::Loop => format!("{}loop", label),
+            LoopKind::DoWhile => format!("{}do", label),
+        }
     }
 }
 
+// while (cond) {}, loop {} or do {} while (cond);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoopKind {
+    While,
+    Loop,
+    DoWhile,
+}
+
 // IfStatementNode
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct IfStatementNode {
@@ -509,13 +1110,153 @@ impl ReturnNode {
     }
 }
 
+// YieldNode
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct YieldNode {
+    pub value: Box<Node>,
+}
+impl YieldNode {
+    pub fn new(value: Box<Node>) -> Node {
+        Node::Yield(YieldNode { value })
+    }
+}
+
+impl Node {
+    // Whether a yield can be reached from this statement without entering
+    // another function, which makes the enclosing function a generator
+    pub fn contains_yield(&self) -> bool {
+        match self {
+            Node::Yield(_) => true,
+            Node::BlockStatement(_node) => _node.value.iter().any(Node::contains_yield),
+            Node::WhileLoop(_node) => _node.body.contains_yield(),
+            Node::IfStatement(_node) => {
+                _node.true_block.contains_yield()
+                    || _node.elif_blocks.iter().any(Node::contains_yield)
+                    || _node
+                        .else_block
+                        .as_ref()
+                        .is_some_and(|_b| _b.contains_yield())
+            }
+            Node::ElifStatement(_node) => _node.true_block.contains_yield(),
+            _ => false,
+        }
+    }
+
+    // Collects the variables the node reads that it didn't declare itself,
+    // `bound` holds the names declared so far
+    pub fn free_variables(&self, bound: &mut Vec<String>, free: &mut Vec<String>) {
+        match self {
+            Node::Identifier(_id) if !bound.contains(&_id.value) && !free.contains(&_id.value) => {
+                free.push(_id.value.clone());
+            }
+            Node::List(ListNode { elements })
+            | Node::Set(SetNode { elements })
+            | Node::Tuple(TupleNode { elements }) => all_free_variables(elements, bound, free),
+            Node::Map(_node) => _node.elements.iter().for_each(|(_k, _v)| {
+                _k.free_variables(bound, free);
+                _v.free_variables(bound, free);
+            }),
+            Node::ListComprehension(_node) => {
+                let outer = bound.len();
+                clause_free_variables(&_node.clauses, bound, free);
+                _node.element.free_variables(bound, free);
+                bound.truncate(outer);
+            }
+            Node::MapComprehension(_node) => {
+                let outer = bound.len();
+                clause_free_variables(&_node.clauses, bound, free);
+                _node.key.free_variables(bound, free);
+                _node.value.free_variables(bound, free);
+                bound.truncate(outer);
+            }
+            Node::UnaryNumber(UnaryNumberNode { value, .. })
+            | Node::UnaryBoolean(UnaryBooleanNode { value, .. })
+            | Node::NamedArgument(NamedArgumentNode { value, .. })
+            | Node::Spread(SpreadNode { value })
+            | Node::FieldAccess(FieldAccessNode { value, .. })
+            | Node::Yield(YieldNode { value }) => value.free_variables(bound, free),
+            Node::BinaryOpNumber(BinaryOpNumberNode { left, right, .. })
+            | Node::BinaryOpBoolean(BinaryOpBooleanNode { left, right, .. }) => {
+                left.free_variables(bound, free);
+                right.free_variables(bound, free);
+            }
+            Node::Assignment(_node) => {
+                // x += 1 reads x as well
+                if _node.assign_type != Assignment::Equals
+                    && !bound.contains(&_node.id)
+                    && !free.contains(&_node.id)
+                {
+                    free.push(_node.id.clone());
+                }
+                _node.value.free_variables(bound, free);
+            }
+            Node::MultiDeclaration(_node) => all_free_variables(&_node.declarations, bound, free),
+            Node::Declaration(_node) => {
+                _node.value.free_variables(bound, free);
+                bound.push(_node.id.clone());
+            }
+            Node::Destructure(_node) => {
+                _node.value.free_variables(bound, free);
+                if _node.declare {
+                    _node.pattern.names(bound);
+                }
+            }
+            Node::BlockStatement(_node) => {
+                let outer = bound.len();
+                all_free_variables(&_node.value, bound, free);
+                bound.truncate(outer);
+            }
+            Node::FunctionCall(_node) => all_free_variables(&_node.args, bound, free),
+            Node::Pipe(PipeNode { value, call })
+            | Node::MethodCall(MethodCallNode { value, call }) => {
+                value.free_variables(bound, free);
+                all_free_variables(&call.args, bound, free);
+            }
+            Node::FunctionDeclaration(_node) => {
+                let outer = bound.len();
+                for param in _node.params.iter() {
+                    if let Some(default) = &param.default {
+                        default.free_variables(bound, free);
+                    }
+                    bound.push(param.id.clone());
+                }
+                _node.body.free_variables(bound, free);
+                bound.truncate(outer);
+            }
+            Node::WhileLoop(_node) => {
+                _node.condition.free_variables(bound, free);
+                _node.body.free_variables(bound, free);
+            }
+            Node::IfStatement(_node) => {
+                _node.condition.free_variables(bound, free);
+                _node.true_block.free_variables(bound, free);
+                all_free_variables(&_node.elif_blocks, bound, free);
+                if let Some(else_block) = &_node.else_block {
+                    else_block.free_variables(bound, free);
+                }
+            }
+            Node::ElifStatement(_node) => {
+                _node.condition.free_variables(bound, free);
+                _node.true_block.free_variables(bound, free);
+            }
+            Node::Return(_node) => {
+                if let Some(res) = &_node.res {
+                    res.free_variables(bound, free);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 impl Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Node::Int(_i) => write!(f, "{}", _i.value),
-            Node::Double(_d) => write!(f, "{}", _d.value),
+            Node::Int(_i) => write!(f, "{}", _i.raw),
+            Node::BigInt(_i) => write!(f, "{}", _i.raw),
+            Node::Double(_d) => write!(f, "{}", _d.raw),
             Node::Boolean(_b) => write!(f, "{}", _b.value),
-            Node::String(_s) => write!(f, "\"{}\"", _s.value),
+            Node::String(_s) => write!(f, "{}", _s.raw),
             Node::Comment(_s) => write!(f, "// {}", _s.value.trim_start()),
             Node::Identifier(_id) => write!(f, "{}", _id.value),
             Node::UnaryNumber(_un) => write!(f, "{}{}", _un.op, _un.value),
@@ -523,7 +1264,19 @@ impl Display for Node {
             Node::BinaryOpNumber(_bon) => write!(f, "{} {} {}", _bon.left, _bon.op, _bon.right),
             Node::BinaryOpBoolean(_bob) => write!(f, "{} {} {}", _bob.left, _bob.op, _bob.right),
             Node::Assignment(_a) => write!(f, "{} {} {}", _a.id, _a.assign_type, _a.value),
-            Node::Declaration(_dec) => write!(f, "let {} = {};", _dec.id, _dec.value),
+            Node::Declaration(_dec) => write!(f, "let {} = {};", _dec.target(), _dec.value),
+            Node::Destructure(_des) => {
+                if _des.declare {
+                    write!(
+                        f,
+                        "let {} = {};",
+                        _des.pattern.declaration_target(),
+                        _des.value
+                    )
+                } else {
+                    write!(f, "{} = {};", _des.pattern, _des.value)
+                }
+            }
             Node::MultiDeclaration(_mdec) => {
                 write!(f, "let ")?;
                 write!(
@@ -532,11 +1285,12 @@ impl Display for Node {
                     _mdec
                         .declarations
                         .iter()
-                        .map(|_d| {
-                            if let Node::Declaration(_dec) = _d {
-                                return format!("{} = {}", _dec.id, _dec.value);
+                        .map(|_d| match _d {
+                            Node::Declaration(_dec) => format!("{} = {}", _dec.id, _dec.value),
+                            Node::Destructure(_des) => {
+                                format!("{} = {}", _des.pattern.declaration_target(), _des.value)
                             }
-                            String::new()
+                            _ => String::new(),
                         })
                         .collect::<Vec<String>>()
                         .join(", ")
@@ -555,6 +1309,49 @@ impl Display for Node {
                 panic!("Default BlockStatement");
             }
             Node::FunctionDeclaration(_fnd) => write!(f, "fn {}() {{{}}}", _fnd.id, _fnd.body),
+            Node::FunctionSignature(_fns) => write!(
+                f,
+                "fn {}({}){};",
+                _fns.id,
+                _fns.params
+                    .iter()
+                    .map(|_p| format!("{}", _p))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                returns(&_fns.ret)
+            ),
+            Node::StructDeclaration(_sd) => {
+                write!(
+                    f,
+                    "struct {} {{ {} }}",
+                    _sd.id,
+                    _sd.fields
+                        .iter()
+                        .map(|(_id, _ty)| typed(_id, _ty))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+            Node::TraitDeclaration(_td) => write!(
+                f,
+                "trait {} {{{}}}",
+                _td.id,
+                BlockStatementNode::new(_td.methods.clone())
+            ),
+            Node::Impl(_im) => write!(
+                f,
+                "{} {{{}}}",
+                _im.header(),
+                BlockStatementNode::new(_im.methods.clone())
+            ),
+            Node::FieldAccess(_fa) => match *_fa.value {
+                Node::UnaryNumber(_)
+                | Node::UnaryBoolean(_)
+                | Node::BinaryOpNumber(_)
+                | Node::BinaryOpBoolean(_)
+                | Node::Pipe(_) => write!(f, "({}).{}", _fa.value, _fa.field),
+                _ => write!(f, "{}.{}", _fa.value, _fa.field),
+            },
             Node::FunctionCall(_fnc) => {
                 write!(f, "{}(", _fnc.id)?;
                 write!(
@@ -568,6 +1365,49 @@ impl Display for Node {
                 )?;
                 write!(f, ")")
             }
+            Node::Tuple(_t) if _t.elements.len() == 1 => write!(f, "({},)", _t.elements[0]),
+            Node::Tuple(_t) => write!(
+                f,
+                "({})",
+                _t.elements
+                    .iter()
+                    .map(|_e| format!("{}", _e))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Node::Set(_s) => write!(
+                f,
+                "#{{{}}}",
+                _s.elements
+                    .iter()
+                    .map(|_e| format!("{}", _e))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Node::ListComprehension(_lc) => {
+                write!(f, "[{} {}]", _lc.element, join_clauses(&_lc.clauses))
+            }
+            Node::MapComprehension(_mc) => write!(
+                f,
+                "{{{}: {} {}}}",
+                _mc.key,
+                _mc.value,
+                join_clauses(&_mc.clauses)
+            ),
+            Node::Pipe(_p) => write!(f, "{} |> {}", _p.value, Node::FunctionCall(_p.call.clone())),
+            Node::MethodCall(_mc) => {
+                let call = Node::FunctionCall(_mc.call.clone());
+                match *_mc.value {
+                    Node::UnaryNumber(_)
+                    | Node::UnaryBoolean(_)
+                    | Node::BinaryOpNumber(_)
+                    | Node::BinaryOpBoolean(_)
+                    | Node::Pipe(_) => write!(f, "({}).{}", _mc.value, call),
+                    _ => write!(f, "{}.{}", _mc.value, call),
+                }
+            }
+            Node::NamedArgument(_na) => write!(f, "{}: {}", _na.id, _na.value),
+            Node::Spread(_s) => write!(f, "..{}", _s.value),
             Node::WhileLoop(_) => todo!("WhileLoop"),
             Node::IfStatement(_) => todo!("IfStatement"),
             Node::ElifStatement(_) => todo!("ElifStatement"),
@@ -578,8 +1418,15 @@ impl Display for Node {
                     write!(f, "return;")
                 }
             }
-            Node::Break => write!(f, "break;"),
-            Node::Continue => write!(f, "continue;"),
+            Node::Yield(_y) => write!(f, "yield {};", _y.value),
+            Node::Break(_label) => match _label {
+                Some(_l) => write!(f, "break {};", _l),
+                None => write!(f, "break;"),
+            },
+            Node::Continue(_label) => match _label {
+                Some(_l) => write!(f, "continue {};", _l),
+                None => write!(f, "continue;"),
+            },
             Node::List(_l) => {
                 write!(f, "[")?;
                 write!(
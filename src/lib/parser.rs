@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use std::collections::HashSet;
 
 use ordered_float::OrderedFloat;
 
@@ -8,6 +9,7 @@ use crate::utils::{get_assignment, get_assignment_from_token};
 use super::nodes::*;
 use super::tokens::Token;
 use super::utils::variant_eq;
+use super::values::Value;
 
 type ParseResult = Result<Node, String>;
 
@@ -15,11 +17,20 @@ type ParseResult = Result<Node, String>;
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    // Labels of the enclosing loops, innermost last
+    loops: Vec<Option<String>>,
+    // Whether a function body is being parsed, return and yield need one
+    in_function: bool,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+        Parser {
+            tokens,
+            pos: 0,
+            loops: vec![],
+            in_function: false,
+        }
     }
 
     fn panic_invalid_syntax(&self, message: &str) -> String {
@@ -79,73 +90,66 @@ impl Parser {
 
     pub fn parse(&mut self) -> ParseResult {
         let mut res = Vec::new();
+        let mut spans = Vec::new();
         while !variant_eq(self.curr(), &Token::EOF(PosRange::empty())) {
+            let from = self.pos;
             res.push(self.primary_statements()?);
+            spans.push(self.span(from));
         }
         if !variant_eq(self.curr(), &Token::EOF(PosRange::empty())) {
             return Err(format!("Invalid Syntax {}", self.curr().get_pos()));
         }
-        // Invoke Main
-        res.push(FunctionCallNode::new(String::from("main"), Vec::new()));
 
-        Ok(BlockStatementNode::new(res))
+        Ok(BlockStatementNode::with_spans(res, spans))
+    }
+
+    // Source range from the token at `from` up to the last consumed token
+    fn span(&self, from: usize) -> PosRange {
+        let start = self.tokens[from].pos_range().from.clone();
+        let last = self.tokens[self.pos.max(from + 1) - 1].pos_range();
+        let end = last.to.clone().unwrap_or(last.from.clone());
+        PosRange::new(start, Some(end))
     }
 
+    // Top level statements run in order, main is called after them if declared
     fn primary_statements(&mut self) -> ParseResult {
         match self.curr() {
-            Token::Identifier(_, _) => {
-                // Assignment or FunctionCall
-                match self.lookahead()? {
-                    _ if self.contains_tkn(get_assignment(), self.lookahead()?) => {
-                        self.assignment()
-                    }
-                    Token::LParan(_) => {
-                        let res = self.function_call_statement();
-                        self.eat(&Token::Semicolon(PosRange::empty()))?;
-                        res
-                    }
-                    _ => {
-                        let res = self.logical_and_or()?;
-                        self.eat(&Token::Semicolon(PosRange::empty()))?;
-                        Ok(res)
-                    }
-                }
-            }
-            Token::FnK(_) => {
-                // Function Declaration
-                self.function_declaration_statement(false)
-            }
-            Token::CFnK(_) => {
-                // Function Declaration
-                self.function_declaration_statement(true)
+            Token::StructK(_) => {
+                // Struct Declaration
+                self.struct_declaration()
             }
-            Token::LetK(_) => {
-                // Variable Declaration
-                self.multi_declaration_node()
+            Token::TraitK(_) => {
+                // Trait Declaration
+                self.trait_declaration()
             }
-            Token::Comment(value, _) => {
-                // Comment Node
-                self.comment(value.to_owned())
+            Token::ImplK(_) => {
+                // Impl Block
+                self.impl_declaration()
             }
-            _ => Err(self.panic_invalid_syntax(
-                "Only (Variable and Function) Declaration are allowed in Global Scope",
-            )),
+            _ => self.control_flow_statement(),
         }
     }
 
-    fn control_flow_statement(&mut self, in_loop: bool) -> ParseResult {
+    fn control_flow_statement(&mut self) -> ParseResult {
+        if variant_eq(self.curr(), &Token::LSquare(PosRange::empty()))
+            && self.is_destructuring_assignment()
+        {
+            // Destructuring Assignment
+            return self.destructuring_assignment();
+        }
         match self.curr() {
+            Token::Identifier(_, _)
+                if variant_eq(self.lookahead()?, &Token::Colon(PosRange::empty())) =>
+            {
+                // Labeled Loop
+                self.loop_statement()
+            }
             Token::Identifier(_, _) => {
                 // Assignment or FunctionCall
                 match self.lookahead()? {
                     _ if self.contains_tkn(get_assignment(), self.lookahead()?) => {
                         self.assignment()
                     }
-                    Token::LParan(_) => {
-                        let res = self.function_call_statement();
-                        self.eat(&Token::Semicolon(PosRange::empty()))?;
-                        res
-                    }
                     _ => {
                         let res = self.logical_and_or();
                         self.eat(&Token::Semicolon(PosRange::empty()))?;
@@ -153,13 +157,9 @@ impl Parser {
                     }
                 }
             }
-            Token::FnK(_) => {
+            Token::FnK(_) | Token::CFnK(_) | Token::At(_) => {
                 // Function Declaration
-                self.function_declaration_statement(false)
-            }
-            Token::CFnK(_) => {
-                // Function Declaration
-                self.function_declaration_statement(true)
+                self.function_declaration_statement()
             }
             Token::LetK(_) => {
                 // Variable Declaration
@@ -167,27 +167,34 @@ impl Parser {
             }
             Token::LBrace(_) => {
                 // Block Statement
-                self.block_statement(in_loop)
+                self.block_statement()
             }
             Token::IfK(_) => {
                 // If Statement
-                self.if_statement(in_loop)
+                self.if_statement()
             }
-            Token::WhileK(_) => {
-                // While Loop
-                self.while_loop_statement()
+            Token::WhileK(_) | Token::LoopK(_) | Token::DoK(_) => {
+                // While, Loop or Do-While Loop
+                self.loop_statement()
+            }
+            Token::ReturnK(_) | Token::YieldK(_) if !self.in_function => {
+                Err(self.panic_invalid_syntax("can only be used inside functions"))
             }
             Token::ReturnK(_) => {
                 // Return Statement
                 self.return_statement()
             }
+            Token::YieldK(_) => {
+                // Yield Statement
+                self.yield_statement()
+            }
             Token::BreakK(_) => {
                 // Break Statement
-                self.break_statement(in_loop)
+                self.loop_jump(true)
             }
             Token::ContinueK(_) => {
                 // Continue Statement
-                self.continue_statement(in_loop)
+                self.loop_jump(false)
             }
             Token::Comment(value, _) => {
                 // Comment Node
@@ -210,37 +217,62 @@ impl Parser {
         self.eat(&Token::ReturnK(PosRange::empty()))?;
         let mut res = None;
         if !variant_eq(self.curr(), &Token::Semicolon(PosRange::empty())) {
-            res = Some(Box::new(self.logical_and_or()?));
+            let mut values = vec![self.logical_and_or()?];
+            // return a, b; returns a Tuple
+            while variant_eq(self.curr(), &Token::Comma(PosRange::empty())) {
+                self.eat(&Token::Comma(PosRange::empty()))?;
+                values.push(self.logical_and_or()?);
+            }
+            res = Some(Box::new(if values.len() == 1 {
+                values.remove(0)
+            } else {
+                TupleNode::new(values)
+            }));
         }
         self.eat(&Token::Semicolon(PosRange::empty()))?;
         Ok(ReturnNode::new(res))
     }
 
-    fn break_statement(&mut self, in_loop: bool) -> ParseResult {
-        if !in_loop {
-            return Err(self.panic_invalid_syntax("can only be used inside loops"));
-        }
-        self.eat(&Token::BreakK(PosRange::empty()))?;
+    fn yield_statement(&mut self) -> ParseResult {
+        self.eat(&Token::YieldK(PosRange::empty()))?;
+        let value = Box::new(self.logical_and_or()?);
         self.eat(&Token::Semicolon(PosRange::empty()))?;
-        Ok(Node::Break)
+        Ok(YieldNode::new(value))
     }
 
-    fn continue_statement(&mut self, in_loop: bool) -> ParseResult {
-        if !in_loop {
+    // break or continue, optionally naming the loop it applies to
+    fn loop_jump(&mut self, is_break: bool) -> ParseResult {
+        if self.loops.is_empty() {
             return Err(self.panic_invalid_syntax("can only be used inside loops"));
         }
-        self.eat(&Token::ContinueK(PosRange::empty()))?;
+        self.next()?;
+        let mut label = None;
+        if let Token::Identifier(id, _) = self.curr() {
+            let id = id.to_owned();
+            let pos = self.curr().get_pos();
+            if !self.loops.contains(&Some(id.clone())) {
+                return Err(format!(
+                    "Invalid Syntax {pos}: '{id}' is not the label of an enclosing loop"
+                ));
+            }
+            self.next()?;
+            label = Some(id);
+        }
         self.eat(&Token::Semicolon(PosRange::empty()))?;
-        Ok(Node::Continue)
+        if is_break {
+            Ok(Node::Break(label))
+        } else {
+            Ok(Node::Continue(label))
+        }
     }
 
-    fn if_statement(&mut self, in_loop: bool) -> ParseResult {
+    fn if_statement(&mut self) -> ParseResult {
         // Parse if condition
         self.eat(&Token::IfK(PosRange::empty()))?;
         self.eat(&Token::LParan(PosRange::empty()))?;
         let condition = Box::new(self.logical_and_or()?);
         self.eat(&Token::RParan(PosRange::empty()))?;
-        let true_block = Box::new(self.block_statement(in_loop)?);
+        let true_block = Box::new(self.block_statement()?);
 
         // Parse elif statements
         let mut elif_blocks: Vec<Node> = Vec::new();
@@ -252,7 +284,7 @@ impl Parser {
 
             let condition = Box::new(self.logical_and_or()?);
             self.eat(&Token::RParan(PosRange::empty()))?;
-            let true_block = Box::new(self.block_statement(in_loop)?);
+            let true_block = Box::new(self.block_statement()?);
             elif_blocks.push(ElifStatementNode::new(condition, true_block));
         }
 
@@ -260,7 +292,7 @@ impl Parser {
         let mut else_block = None;
         if variant_eq(self.curr(), &Token::ElseK(PosRange::empty())) {
             self.eat(&Token::ElseK(PosRange::empty()))?;
-            else_block = Some(Box::new(self.block_statement(in_loop)?));
+            else_block = Some(Box::new(self.block_statement()?));
         }
         Ok(IfStatementNode::new(
             condition,
@@ -270,91 +302,506 @@ impl Parser {
         ))
     }
 
-    // While Loop
-    fn while_loop_statement(&mut self) -> ParseResult {
-        self.eat(&Token::WhileK(PosRange::empty()))?;
+    // [label:] while (cond) {}, loop {} or do {} while (cond);
+    fn loop_statement(&mut self) -> ParseResult {
+        let mut label = None;
+        if let Token::Identifier(id, _) = self.curr() {
+            label = Some(id.to_owned());
+            self.next()?;
+            self.eat(&Token::Colon(PosRange::empty()))?;
+        }
 
-        self.eat(&Token::LParan(PosRange::empty()))?;
-        let condition = self.logical_and_or()?;
-        self.eat(&Token::RParan(PosRange::empty()))?;
+        let (kind, mut condition) = match self.curr() {
+            Token::WhileK(_) => {
+                self.next()?;
+                self.eat(&Token::LParan(PosRange::empty()))?;
+                let condition = self.logical_and_or()?;
+                self.eat(&Token::RParan(PosRange::empty()))?;
+                (LoopKind::While, condition)
+            }
+            Token::LoopK(_) => {
+                self.next()?;
+                (LoopKind::Loop, Node::Boolean(BooleanNode { value: true }))
+            }
+            Token::DoK(_) => {
+                self.next()?;
+                (
+                    LoopKind::DoWhile,
+                    Node::Boolean(BooleanNode { value: true }),
+                )
+            }
+            _ => return Err(self.panic_invalid_syntax("expected a loop after label")),
+        };
 
-        let body = self.block_statement(true)?;
+        self.loops.push(label.clone());
+        let body = self.block_statement();
+        self.loops.pop();
+        let body = body?;
 
-        Ok(WhileLoopNode::new(Box::new(condition), Box::new(body)))
+        if kind == LoopKind::DoWhile {
+            self.eat(&Token::WhileK(PosRange::empty()))?;
+            self.eat(&Token::LParan(PosRange::empty()))?;
+            condition = self.logical_and_or()?;
+            self.eat(&Token::RParan(PosRange::empty()))?;
+            self.eat(&Token::Semicolon(PosRange::empty()))?;
+        }
+
+        Ok(WhileLoopNode::new(
+            Box::new(condition),
+            Box::new(body),
+            kind,
+            label,
+        ))
     }
 
-    // Parses a Function Declaration Statement
-    fn function_declaration_statement(&mut self, is_cached: bool) -> ParseResult {
-        if is_cached {
-            self.eat(&Token::CFnK(PosRange::empty()))?;
+    // Parses a Function Declaration Statement along with its annotations
+    fn function_declaration_statement(&mut self) -> ParseResult {
+        let mut annotations = self.annotations()?;
+        if variant_eq(self.curr(), &Token::CFnK(PosRange::empty())) {
+            // cfn is sugar for @memo
+            if annotations.iter().any(|_a| _a.id == "memo") {
+                return Err(self.panic_invalid_syntax("is already memoized by @memo"));
+            }
+            self.next()?;
+            annotations.insert(0, Annotation::new("memo", vec![]));
         } else {
             self.eat(&Token::FnK(PosRange::empty()))?;
         }
 
-        // Parses Function name
+        // Parses Function name and params
         let id = self.identifier()?;
+        let params = self.params()?;
+        let ret = self.return_annotation()?;
+        self.function_body(annotations, id, params, ret)
+    }
+
+    // @memo(max: 100), @trace, @deprecated("use x") and @pure
+    fn annotations(&mut self) -> Result<Vec<Annotation>, String> {
+        let mut annotations: Vec<Annotation> = Vec::new();
+        while variant_eq(self.curr(), &Token::At(PosRange::empty())) {
+            self.next()?;
+            let pos = self.curr().get_pos();
+            let id = self.identifier()?;
+            let mut args = Vec::new();
+            if variant_eq(self.curr(), &Token::LParan(PosRange::empty())) {
+                self.next()?;
+                while !variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
+                    if !args.is_empty() {
+                        self.eat(&Token::Comma(PosRange::empty()))?;
+                    }
+                    // Named arguments can also be written as max = 100
+                    if let (Token::Identifier(_id, _), Ok(Token::Equals(_))) =
+                        (self.curr(), self.lookahead())
+                    {
+                        let _id = _id.clone();
+                        self.next()?;
+                        self.next()?;
+                        args.push(NamedArgumentNode::new(
+                            _id,
+                            Box::new(self.logical_and_or()?),
+                        ));
+                    } else {
+                        args.push(self.argument(&args)?);
+                    }
+                }
+                self.eat(&Token::RParan(PosRange::empty()))?;
+            }
 
-        // Parses Function params
+            let valid = match (id.as_str(), &args[..]) {
+                ("memo", [Node::NamedArgument(_na)]) => {
+                    _na.id == "max" && matches!(_na.value.as_ref(), Node::Int(_i) if _i.value > 0)
+                }
+                ("memo", [Node::Int(_i)]) => _i.value > 0,
+                ("deprecated", [Node::String(_)]) => true,
+                ("memo" | "deprecated" | "trace" | "pure", []) => true,
+                ("memo", _) => {
+                    return Err(format!(
+                        "Invalid Syntax {pos}: '@memo' expects a positive Int max"
+                    ))
+                }
+                ("deprecated", _) => {
+                    return Err(format!(
+                        "Invalid Syntax {pos}: '@deprecated' expects a String message"
+                    ))
+                }
+                ("trace" | "pure", _) => false,
+                _ => return Err(format!("Invalid Syntax {pos}: Unknown annotation '@{id}'")),
+            };
+            if !valid {
+                return Err(format!(
+                    "Invalid Syntax {pos}: Invalid arguments for '@{id}'"
+                ));
+            }
+            if annotations.iter().any(|_a| _a.id == id) {
+                return Err(format!("Invalid Syntax {pos}: '@{id}' is applied twice"));
+            }
+            annotations.push(Annotation::new(&id, args));
+        }
+        Ok(annotations)
+    }
+
+    fn params(&mut self) -> Result<Vec<Param>, String> {
         self.eat(&Token::LParan(PosRange::empty()))?;
         let mut params = Vec::new();
 
-        if let Token::Identifier(_, _) = self.curr() {
-            let param = self.identifier()?;
-            params.push(param);
+        if !variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
+            params.push(self.param(&params)?);
         }
 
         while self.pos < self.tokens.len()
             && !variant_eq(self.curr(), &Token::RParan(PosRange::empty()))
         {
             self.eat(&Token::Comma(PosRange::empty()))?;
-            let param = self.identifier()?;
-            params.push(param);
+            params.push(self.param(&params)?);
         }
         self.eat(&Token::RParan(PosRange::empty()))?;
+        Ok(params)
+    }
 
+    fn function_body(
+        &mut self,
+        annotations: Vec<Annotation>,
+        id: String,
+        params: Vec<Param>,
+        ret: Option<Type>,
+    ) -> ParseResult {
         // Parses Function body
-        let body = Box::new(self.block_statement(false)?);
+        // Loops outside the function can't be broken out of from inside it
+        let loops = std::mem::take(&mut self.loops);
+        let in_function = std::mem::replace(&mut self.in_function, true);
+        let body = self.block_statement();
+        self.loops = loops;
+        self.in_function = in_function;
+        let body = Box::new(body?);
+
+        if body.contains_yield() && annotations.iter().any(|_a| _a.id == "memo") {
+            return Err(format!(
+                "Invalid Syntax: generator function '{}' can't be memoized",
+                id
+            ));
+        }
+        Ok(FunctionDeclarationNode::new(
+            id,
+            params,
+            ret,
+            body,
+            annotations,
+        ))
+    }
+
+    // struct Point { x, y }
+    fn struct_declaration(&mut self) -> ParseResult {
+        self.eat(&Token::StructK(PosRange::empty()))?;
+        let id = self.identifier()?;
+        self.eat(&Token::LBrace(PosRange::empty()))?;
+        let mut fields: Vec<(String, Option<Type>)> = Vec::new();
+        while !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+            if !fields.is_empty() {
+                self.eat(&Token::Comma(PosRange::empty()))?;
+                if variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+                    break;
+                }
+            }
+            if let Token::Identifier(_field, _) = self.curr() {
+                if fields.iter().any(|(_id, _)| _id == _field) {
+                    return Err(self.panic_invalid_syntax("field is declared twice"));
+                }
+            }
+            let field = self.identifier()?;
+            fields.push((field, self.annotation()?));
+        }
+        self.eat(&Token::RBrace(PosRange::empty()))?;
+        Ok(StructDeclarationNode::new(id, fields))
+    }
+
+    // trait Shape { fn area(self); fn name(self) { return "shape"; } }
+    fn trait_declaration(&mut self) -> ParseResult {
+        self.eat(&Token::TraitK(PosRange::empty()))?;
+        let id = self.identifier()?;
+        let methods = self.methods(true)?;
+        Ok(TraitDeclarationNode::new(id, methods))
+    }
+
+    // impl Point { ... } or impl Trait for Point { ... }
+    fn impl_declaration(&mut self) -> ParseResult {
+        self.eat(&Token::ImplK(PosRange::empty()))?;
+        let mut trait_id = None;
+        let mut type_id = self.identifier()?;
+        if variant_eq(self.curr(), &Token::ForK(PosRange::empty())) {
+            self.next()?;
+            trait_id = Some(type_id);
+            type_id = self.identifier()?;
+        }
+        let methods = self.methods(false)?;
+        Ok(ImplNode::new(trait_id, type_id, methods))
+    }
+
+    // Method declarations between braces, signatures without a body only in traits
+    fn methods(&mut self, in_trait: bool) -> Result<Vec<Node>, String> {
+        self.eat(&Token::LBrace(PosRange::empty()))?;
+        let mut methods = Vec::new();
+        while !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+            let method = match self.curr() {
+                Token::Comment(value, _) => self.comment(value.to_owned())?,
+                Token::CFnK(_) | Token::At(_) => self.function_declaration_statement()?,
+                Token::FnK(_) => {
+                    self.next()?;
+                    let id = self.identifier()?;
+                    let params = self.params()?;
+                    let ret = self.return_annotation()?;
+                    if in_trait && variant_eq(self.curr(), &Token::Semicolon(PosRange::empty())) {
+                        self.next()?;
+                        FunctionSignatureNode::new(id, params, ret)
+                    } else {
+                        self.function_body(vec![], id, params, ret)?
+                    }
+                }
+                _ => return Err(self.panic_invalid_syntax("Expected a method declaration")),
+            };
+            methods.push(method);
+        }
+        self.eat(&Token::RBrace(PosRange::empty()))?;
+        Ok(methods)
+    }
+
+    // Parses a Function param: name, name = default or ..name
+    fn param(&mut self, prev: &[Param]) -> Result<Param, String> {
+        if prev.iter().any(|_p| _p.rest) {
+            return Err(self.panic_invalid_syntax("rest parameter must be last"));
+        }
+        if variant_eq(self.curr(), &Token::DotDot(PosRange::empty())) {
+            self.eat(&Token::DotDot(PosRange::empty()))?;
+            let mut param = Param::rest(self.identifier()?);
+            param.ty = self.annotation()?;
+            return Ok(param);
+        }
 
-        if is_cached {
-            Ok(FunctionDeclarationNode::new_cfn(id, params, body))
+        let id = self.identifier()?;
+        if prev.iter().any(|_p| _p.id == id) {
+            return Err(format!("Invalid Syntax: duplicate parameter '{}'", id));
+        }
+        let ty = self.annotation()?;
+        let mut param = if variant_eq(self.curr(), &Token::Equals(PosRange::empty())) {
+            self.eat(&Token::Equals(PosRange::empty()))?;
+            let default = Box::new(self.logical_and_or()?);
+            Param::with_default(id, default)
+        } else if prev.iter().any(|_p| _p.default.is_some()) {
+            return Err(self
+                .panic_invalid_syntax("parameter without default follows parameter with default"));
         } else {
-            Ok(FunctionDeclarationNode::new_fn(id, params, body))
+            Param::new(id)
+        };
+        param.ty = ty;
+        Ok(param)
+    }
+
+    // `: Type` after a name, if present
+    fn annotation(&mut self) -> Result<Option<Type>, String> {
+        if !variant_eq(self.curr(), &Token::Colon(PosRange::empty())) {
+            return Ok(None);
+        }
+        self.next()?;
+        Ok(Some(self.type_annotation()?))
+    }
+
+    // `-> Type` after the params of a function, if present
+    fn return_annotation(&mut self) -> Result<Option<Type>, String> {
+        if !variant_eq(self.curr(), &Token::Arrow(PosRange::empty())) {
+            return Ok(None);
         }
+        self.next()?;
+        Ok(Some(self.type_annotation()?))
     }
 
-    fn block_statement(&mut self, in_loop: bool) -> ParseResult {
+    // Int, List<Int>, Map<String, List<Double>>, Point, ...
+    fn type_annotation(&mut self) -> Result<Type, String> {
+        let name = match self.curr() {
+            Token::Identifier(_id, _) => _id.clone(),
+            _ => return Err(self.panic_invalid_syntax("Expected a type")),
+        };
+        self.next()?;
+
+        let mut args = Vec::new();
+        if variant_eq(self.curr(), &Token::LessThan(PosRange::empty())) {
+            self.next()?;
+            args.push(self.type_annotation()?);
+            while variant_eq(self.curr(), &Token::Comma(PosRange::empty())) {
+                self.next()?;
+                args.push(self.type_annotation()?);
+            }
+            // `>>` closes two nested type arguments
+            if let Token::ShiftRight(_pos) = self.curr() {
+                self.tokens[self.pos] = Token::GreaterThan(_pos.clone());
+            } else {
+                self.eat(&Token::GreaterThan(PosRange::empty()))?;
+            }
+        }
+
+        let arity = match name.as_str() {
+            "List" | "Set" | "Iterator" => 1,
+            "Map" => 2,
+            "Tuple" => args.len(),
+            _ => 0,
+        };
+        if !args.is_empty() && args.len() != arity {
+            return Err(format!(
+                "Invalid Syntax: '{}' expects {} type arguments, found {}",
+                name,
+                arity,
+                args.len()
+            ));
+        }
+        let mut args = args.into_iter().map(Box::new);
+        let mut arg = || args.next().unwrap_or(Box::new(Type::Any));
+        Ok(match name.as_str() {
+            "Any" => Type::Any,
+            "Int" => Type::Int,
+            "Double" => Type::Double,
+            "Rational" => Type::Rational,
+            "String" => Type::String,
+            "Boolean" => Type::Boolean,
+            "None" => Type::None,
+            "List" => Type::List(arg()),
+            "Map" => Type::Map(arg(), arg()),
+            "Set" => Type::Set(arg()),
+            "Iterator" => Type::Iterator(arg()),
+            "Tuple" => Type::Tuple(args.map(|_t| *_t).collect()),
+            _ => Type::Struct(name),
+        })
+    }
+
+    fn block_statement(&mut self) -> ParseResult {
         let mut value = Vec::new();
+        let mut spans = Vec::new();
         self.eat(&Token::LBrace(PosRange::empty()))?;
         while !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
-            value.push(self.control_flow_statement(in_loop)?);
+            let from = self.pos;
+            value.push(self.control_flow_statement()?);
+            spans.push(self.span(from));
         }
         self.eat(&Token::RBrace(PosRange::empty()))?;
-        Ok(BlockStatementNode::new(value))
+        Ok(BlockStatementNode::with_spans(value, spans))
     }
 
     fn multi_declaration_node(&mut self) -> ParseResult {
         self.eat(&Token::LetK(PosRange::empty()))?;
 
-        let mut declarations = Vec::new();
-
-        let id = self.identifier()?;
-        self.eat(&Token::Equals(PosRange::empty()))?;
-        let value = Box::new(self.logical_and_or()?);
-
-        declarations.push(DeclarationNode::new(id, value));
+        let mut declarations = vec![self.declaration()?];
 
         while self.pos < self.tokens.len()
             && variant_eq(self.curr(), &Token::Comma(PosRange::empty()))
         {
             self.eat(&Token::Comma(PosRange::empty()))?;
-            let id = self.identifier()?;
+            declarations.push(self.declaration()?);
+        }
+
+        self.eat(&Token::Semicolon(PosRange::empty()))?;
+        Ok(MultiDeclarationNode::new(declarations))
+    }
+
+    // Parses `id = value`, `pattern = value` or `a, b = tuple`
+    fn declaration(&mut self) -> ParseResult {
+        if let (Token::Identifier(_, _), Ok(Token::Comma(_))) = (self.curr(), self.lookahead()) {
+            let mut targets = vec![self.pattern()?];
+            while variant_eq(self.curr(), &Token::Comma(PosRange::empty())) {
+                self.eat(&Token::Comma(PosRange::empty()))?;
+                targets.push(self.pattern()?);
+            }
             self.eat(&Token::Equals(PosRange::empty()))?;
             let value = Box::new(self.logical_and_or()?);
-            declarations.push(DeclarationNode::new(id, value));
+            return Ok(DestructureNode::new(Pattern::Tuple(targets), value, true));
+        }
+        match self.curr() {
+            Token::LSquare(_) | Token::LBrace(_) | Token::LParan(_) => {
+                let pattern = self.pattern()?;
+                self.eat(&Token::Equals(PosRange::empty()))?;
+                let value = Box::new(self.logical_and_or()?);
+                Ok(DestructureNode::new(pattern, value, true))
+            }
+            _ => {
+                let id = self.identifier()?;
+                let ty = self.annotation()?;
+                self.eat(&Token::Equals(PosRange::empty()))?;
+                let value = Box::new(self.logical_and_or()?);
+                Ok(DeclarationNode::new(id, ty, value))
+            }
+        }
+    }
+
+    // Parses a destructuring pattern: a, [a, ..rest] or {key, key: pattern}
+    fn pattern(&mut self) -> Result<Pattern, String> {
+        match self.curr() {
+            Token::Identifier(_, _) => Ok(Pattern::Identifier(self.identifier()?)),
+            Token::LSquare(_) => {
+                self.eat(&Token::LSquare(PosRange::empty()))?;
+                let mut elements = Vec::new();
+                let mut rest = None;
+                while !variant_eq(self.curr(), &Token::RSquare(PosRange::empty())) {
+                    if rest.is_some() {
+                        return Err(self.panic_invalid_syntax("rest element must be last"));
+                    }
+                    if variant_eq(self.curr(), &Token::DotDot(PosRange::empty())) {
+                        self.eat(&Token::DotDot(PosRange::empty()))?;
+                        rest = Some(self.identifier()?);
+                    } else {
+                        elements.push(self.pattern()?);
+                    }
+                    if !variant_eq(self.curr(), &Token::RSquare(PosRange::empty())) {
+                        self.eat(&Token::Comma(PosRange::empty()))?;
+                    }
+                }
+                self.eat(&Token::RSquare(PosRange::empty()))?;
+                Ok(Pattern::List(ListPattern { elements, rest }))
+            }
+            Token::LParan(_) => {
+                self.eat(&Token::LParan(PosRange::empty()))?;
+                let mut elements = Vec::new();
+                while !variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
+                    elements.push(self.pattern()?);
+                    if !variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
+                        self.eat(&Token::Comma(PosRange::empty()))?;
+                    }
+                }
+                self.eat(&Token::RParan(PosRange::empty()))?;
+                Ok(Pattern::Tuple(elements))
+            }
+            Token::LBrace(_) => {
+                self.eat(&Token::LBrace(PosRange::empty()))?;
+                let mut entries = Vec::new();
+                while !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+                    let key = self.identifier()?;
+                    if variant_eq(self.curr(), &Token::Colon(PosRange::empty())) {
+                        self.eat(&Token::Colon(PosRange::empty()))?;
+                        entries.push((key, self.pattern()?));
+                    } else {
+                        entries.push((key.clone(), Pattern::Identifier(key)));
+                    }
+                    if !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+                        self.eat(&Token::Comma(PosRange::empty()))?;
+                    }
+                }
+                self.eat(&Token::RBrace(PosRange::empty()))?;
+                Ok(Pattern::Map(MapPattern { entries }))
+            }
+            _ => Err(self.panic_invalid_syntax("Expected identifier, [, ( or {")),
         }
+    }
+
+    // Checks if the tokens at the current position form `[pattern] =`
+    fn is_destructuring_assignment(&mut self) -> bool {
+        let start = self.pos;
+        let res =
+            self.pattern().is_ok() && variant_eq(self.curr(), &Token::Equals(PosRange::empty()));
+        self.pos = start;
+        res
+    }
 
+    fn destructuring_assignment(&mut self) -> ParseResult {
+        let pattern = self.pattern()?;
+        self.eat(&Token::Equals(PosRange::empty()))?;
+        let value = Box::new(self.logical_and_or()?);
         self.eat(&Token::Semicolon(PosRange::empty()))?;
-        Ok(MultiDeclarationNode::new(declarations))
+        Ok(DestructureNode::new(pattern, value, false))
     }
 
     fn assignment(&mut self) -> ParseResult {
@@ -388,34 +835,66 @@ impl Parser {
         let mut args = Vec::new();
 
         if !variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
-            args.push(self.logical_and_or()?);
+            args.push(self.argument(&args)?);
         }
 
         while self.pos < self.tokens.len()
             && !variant_eq(self.curr(), &Token::RParan(PosRange::empty()))
         {
             self.eat(&Token::Comma(PosRange::empty()))?;
-            args.push(self.logical_and_or()?);
+            args.push(self.argument(&args)?);
         }
 
         self.eat(&Token::RParan(PosRange::empty()))?;
         Ok(FunctionCallNode::new(id, args))
     }
 
+    // Parses a call argument: value, ..value or name: value
+    fn argument(&mut self, prev: &[Node]) -> ParseResult {
+        if let (Token::Identifier(id, _), Ok(Token::Colon(_))) = (self.curr(), self.lookahead()) {
+            let id = id.clone();
+            self.next()?;
+            self.eat(&Token::Colon(PosRange::empty()))?;
+            return Ok(NamedArgumentNode::new(id, Box::new(self.logical_and_or()?)));
+        }
+        if prev.iter().any(|_a| matches!(_a, Node::NamedArgument(_))) {
+            return Err(self.panic_invalid_syntax("positional argument follows named argument"));
+        }
+        self.spread_or_expression()
+    }
+
+    // Parses `..value` inside calls and list literals, or a plain expression
+    fn spread_or_expression(&mut self) -> ParseResult {
+        if variant_eq(self.curr(), &Token::DotDot(PosRange::empty())) {
+            self.eat(&Token::DotDot(PosRange::empty()))?;
+            Ok(SpreadNode::new(Box::new(self.logical_and_or()?)))
+        } else {
+            self.logical_and_or()
+        }
+    }
+
     fn list_literal(&mut self) -> ParseResult {
         let mut elements = Vec::new();
 
         self.eat(&Token::LSquare(PosRange::empty()))?;
 
         if !variant_eq(self.curr(), &Token::RSquare(PosRange::empty())) {
-            let elem = self.logical_and_or()?;
+            let elem = self.spread_or_expression()?;
+            if variant_eq(self.curr(), &Token::ForK(PosRange::empty())) {
+                if let Node::Spread(_) = elem {
+                    return Err(self.panic_invalid_syntax("can't spread in a comprehension"));
+                }
+                let clauses = self.comprehension_clauses()?;
+                self.eat(&Token::RSquare(PosRange::empty()))?;
+                return Ok(ListComprehensionNode::new(Box::new(elem), clauses));
+            }
             elements.push(elem);
             if variant_eq(self.curr(), &Token::Comma(PosRange::empty())) {
                 while self.pos < self.tokens.len()
                     && !variant_eq(self.curr(), &Token::RSquare(PosRange::empty()))
                 {
                     self.eat(&Token::Comma(PosRange::empty()))?;
-                    let elem = self.logical_and_or()?;
+                    let elem = self.spread_or_expression()?;
                     elements.push(elem);
                 }
             }
@@ -426,26 +905,74 @@ impl Parser {
         Ok(Node::List(ListNode { elements }))
     }
 
+    // Parses (expr) as grouping, and (), (a,) and (a, b) as Tuples
+    fn tuple_or_group(&mut self) -> ParseResult {
+        self.eat(&Token::LParan(PosRange::empty()))?;
+        let mut elements = Vec::new();
+        while !variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
+            let elem = self.spread_or_expression()?;
+            if elements.is_empty() && variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
+                if let Node::Spread(_) = elem {
+                    return Err(self.panic_invalid_syntax("can't spread inside parentheses"));
+                }
+                self.eat(&Token::RParan(PosRange::empty()))?;
+                return Ok(elem);
+            }
+            elements.push(elem);
+            if !variant_eq(self.curr(), &Token::RParan(PosRange::empty())) {
+                self.eat(&Token::Comma(PosRange::empty()))?;
+            }
+        }
+        self.eat(&Token::RParan(PosRange::empty()))?;
+        Ok(TupleNode::new(elements))
+    }
+
+    fn set_literal(&mut self) -> ParseResult {
+        let mut elements = Vec::new();
+
+        self.eat(&Token::HashLBrace(PosRange::empty()))?;
+        while !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+            elements.push(self.spread_or_expression()?);
+            if !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+                self.eat(&Token::Comma(PosRange::empty()))?;
+            }
+        }
+        self.eat(&Token::RBrace(PosRange::empty()))?;
+
+        Ok(Node::Set(SetNode { elements }))
+    }
+
     fn map_literal(&mut self) -> ParseResult {
-        #[allow(clippy::mutable_key_type)]
-        let mut elements: HashMap<Node, Node> = HashMap::new();
+        let mut elements = IndexMap::new();
+        let mut literals = HashSet::new();
 
         self.eat(&Token::LBrace(PosRange::empty()))?;
 
         if !variant_eq(self.curr(), &Token::RBrace(PosRange::empty())) {
+            let pos = self.curr().get_pos();
             let key = self.logical_and_or()?;
             self.eat(&Token::Colon(PosRange::empty()))?;
             let val = self.logical_and_or()?;
-            elements.insert(key, val);
+            if variant_eq(self.curr(), &Token::ForK(PosRange::empty())) {
+                let clauses = self.comprehension_clauses()?;
+                self.eat(&Token::RBrace(PosRange::empty()))?;
+                return Ok(MapComprehensionNode::new(
+                    Box::new(key),
+                    Box::new(val),
+                    clauses,
+                ));
+            }
+            Self::map_entry(&mut elements, &mut literals, pos, key, val)?;
             if variant_eq(self.curr(), &Token::Comma(PosRange::empty())) {
                 while self.pos < self.tokens.len()
                     && !variant_eq(self.curr(), &Token::RBrace(PosRange::empty()))
                 {
                     self.eat(&Token::Comma(PosRange::empty()))?;
+                    let pos = self.curr().get_pos();
                     let key = self.logical_and_or()?;
                     self.eat(&Token::Colon(PosRange::empty()))?;
                     let val = self.logical_and_or()?;
-                    elements.insert(key, val);
+                    Self::map_entry(&mut elements, &mut literals, pos, key, val)?;
                 }
             }
         }
@@ -455,9 +982,58 @@ impl Parser {
         Ok(Node::Map(MapNode { elements }))
     }
 
+    // A key written twice would silently drop the first value. Literal keys
+    // are compared by value, so `1` and `1.0` are the same key
+    fn map_entry(
+        elements: &mut IndexMap<Node, Node>,
+        literals: &mut HashSet<Value>,
+        pos: String,
+        key: Node,
+        val: Node,
+    ) -> Result<(), String> {
+        let literal = match &key {
+            Node::Int(_i) => Some(Value::IntValue(_i.value)),
+            Node::BigInt(_i) => Some(Value::BigIntValue(_i.value.clone())),
+            Node::Double(_d) => Some(Value::DoubleValue(_d.value)),
+            Node::String(_s) => Some(Value::StringValue(_s.value.clone())),
+            Node::Boolean(_b) => Some(Value::BooleanValue(_b.value)),
+            _ => None,
+        };
+        let repeated = literal.is_some_and(|_l| !literals.insert(_l));
+        if repeated || elements.contains_key(&key) {
+            return Err(format!(
+                "Invalid Syntax {pos}: Duplicate key {key} in map literal"
+            ));
+        }
+        elements.insert(key, val);
+        Ok(())
+    }
+
+    // Parses the `for targets in iterable` and `if cond` clauses of a comprehension
+    fn comprehension_clauses(&mut self) -> Result<Vec<ComprehensionClause>, String> {
+        let mut clauses = Vec::new();
+        while variant_eq(self.curr(), &Token::ForK(PosRange::empty())) {
+            self.eat(&Token::ForK(PosRange::empty()))?;
+            let mut targets = vec![self.pattern()?];
+            while variant_eq(self.curr(), &Token::Comma(PosRange::empty())) {
+                self.eat(&Token::Comma(PosRange::empty()))?;
+                targets.push(self.pattern()?);
+            }
+            self.eat(&Token::InK(PosRange::empty()))?;
+            let iterable = Box::new(self.logical_and_or()?);
+            clauses.push(ComprehensionClause::For { targets, iterable });
+
+            while variant_eq(self.curr(), &Token::IfK(PosRange::empty())) {
+                self.eat(&Token::IfK(PosRange::empty()))?;
+                clauses.push(ComprehensionClause::If(Box::new(self.logical_and_or()?)));
+            }
+        }
+        Ok(clauses)
+    }
+
     // Parses Logical & |
     fn logical_and_or(&mut self) -> ParseResult {
-        let mut res = self.equality()?;
+        let mut res = self.pipe()?;
 
         if self.contains_tkn(
             vec![Token::And(PosRange::empty()), Token::Or(PosRange::empty())],
@@ -465,10 +1041,10 @@ impl Parser {
         ) {
             if variant_eq(self.curr(), &Token::And(PosRange::empty())) {
                 self.next()?;
-                res = BinaryOpBooleanNode::and(Box::new(res), Box::new(self.equality()?));
+                res = BinaryOpBooleanNode::and(Box::new(res), Box::new(self.pipe()?));
             } else if variant_eq(self.curr(), &Token::Or(PosRange::empty())) {
                 self.next()?;
-                res = BinaryOpBooleanNode::or(Box::new(res), Box::new(self.equality()?));
+                res = BinaryOpBooleanNode::or(Box::new(res), Box::new(self.pipe()?));
             }
         }
 
@@ -497,8 +1073,33 @@ impl Parser {
         Ok(res)
     }
 
+    // Parses value |> call(args) and value |> name, binding looser than
+    // comparisons so `a < b |> f()` pipes the Boolean
+    fn pipe(&mut self) -> ParseResult {
+        let mut res = self.equality()?;
+
+        while variant_eq(self.curr(), &Token::Pipe(PosRange::empty())) {
+            self.next()?;
+            let call = match self.equality()? {
+                Node::FunctionCall(_call) => _call,
+                Node::Identifier(_id) => FunctionCallNode {
+                    id: _id.value,
+                    args: Vec::new(),
+                },
+                _ => {
+                    return Err(
+                        self.panic_invalid_syntax("right side of '|>' must be a function call")
+                    )
+                }
+            };
+            res = PipeNode::new(Box::new(res), call);
+        }
+
+        Ok(res)
+    }
+
     fn comparison(&mut self) -> ParseResult {
-        let mut res = self.expression()?;
+        let mut res = self.bitwise()?;
 
         if self.contains_tkn(
             vec![
@@ -511,16 +1112,70 @@ impl Parser {
         ) {
             if variant_eq(self.curr(), &Token::LessThan(PosRange::empty())) {
                 self.next()?;
-                res = BinaryOpBooleanNode::lt(Box::new(res), Box::new(self.expression()?));
+                res = BinaryOpBooleanNode::lt(Box::new(res), Box::new(self.bitwise()?));
             } else if variant_eq(self.curr(), &Token::LessThanEq(PosRange::empty())) {
                 self.next()?;
-                res = BinaryOpBooleanNode::lte(Box::new(res), Box::new(self.expression()?));
+                res = BinaryOpBooleanNode::lte(Box::new(res), Box::new(self.bitwise()?));
             } else if variant_eq(self.curr(), &Token::GreaterThan(PosRange::empty())) {
                 self.next()?;
-                res = BinaryOpBooleanNode::gt(Box::new(res), Box::new(self.expression()?));
+                res = BinaryOpBooleanNode::gt(Box::new(res), Box::new(self.bitwise()?));
             } else if variant_eq(self.curr(), &Token::GreaterThanEq(PosRange::empty())) {
                 self.next()?;
-                res = BinaryOpBooleanNode::gte(Box::new(res), Box::new(self.expression()?));
+                res = BinaryOpBooleanNode::gte(Box::new(res), Box::new(self.bitwise()?));
+            }
+        }
+
+        Ok(res)
+    }
+
+    // Parses .&. .|. .^.
+    fn bitwise(&mut self) -> ParseResult {
+        let mut res = self.shift()?;
+
+        while self.pos < self.tokens.len()
+            && self.contains_tkn(
+                vec![
+                    Token::BitAnd(PosRange::empty()),
+                    Token::BitOr(PosRange::empty()),
+                    Token::BitXor(PosRange::empty()),
+                ],
+                self.curr(),
+            )
+        {
+            if variant_eq(self.curr(), &Token::BitAnd(PosRange::empty())) {
+                self.next()?;
+                res = BinaryOpNumberNode::bit_and(Box::new(res), Box::new(self.shift()?));
+            } else if variant_eq(self.curr(), &Token::BitOr(PosRange::empty())) {
+                self.next()?;
+                res = BinaryOpNumberNode::bit_or(Box::new(res), Box::new(self.shift()?));
+            } else if variant_eq(self.curr(), &Token::BitXor(PosRange::empty())) {
+                self.next()?;
+                res = BinaryOpNumberNode::bit_xor(Box::new(res), Box::new(self.shift()?));
+            }
+        }
+
+        Ok(res)
+    }
+
+    // Parses << >>
+    fn shift(&mut self) -> ParseResult {
+        let mut res = self.expression()?;
+
+        while self.pos < self.tokens.len()
+            && self.contains_tkn(
+                vec![
+                    Token::ShiftLeft(PosRange::empty()),
+                    Token::ShiftRight(PosRange::empty()),
+                ],
+                self.curr(),
+            )
+        {
+            if variant_eq(self.curr(), &Token::ShiftLeft(PosRange::empty())) {
+                self.next()?;
+                res = BinaryOpNumberNode::shift_left(Box::new(res), Box::new(self.expression()?));
+            } else if variant_eq(self.curr(), &Token::ShiftRight(PosRange::empty())) {
+                self.next()?;
+                res = BinaryOpNumberNode::shift_right(Box::new(res), Box::new(self.expression()?));
             }
         }
 
@@ -599,16 +1254,32 @@ impl Parser {
 
         Ok(res)
     }
+    // An atom followed by any number of field accesses and method calls: value.name(args)
     fn atom(&mut self) -> ParseResult {
-        match self.curr() {
-            Token::LParan(_) => {
-                self.eat(&Token::LParan(PosRange::empty()))?;
-                let res = self.logical_and_or()?;
-                self.eat(&Token::RParan(PosRange::empty()))?;
-                Ok(res)
+        let mut res = self.primary()?;
+
+        while variant_eq(self.curr(), &Token::Dot(PosRange::empty())) {
+            self.next()?;
+            if !variant_eq(self.lookahead()?, &Token::LParan(PosRange::empty())) {
+                res = FieldAccessNode::new(Box::new(res), self.identifier()?);
+                continue;
             }
+            let call = match self.function_call_statement()? {
+                Node::FunctionCall(_call) => _call,
+                _ => unreachable!(),
+            };
+            res = MethodCallNode::new(Box::new(res), call);
+        }
+
+        Ok(res)
+    }
+
+    fn primary(&mut self) -> ParseResult {
+        match self.curr() {
+            Token::LParan(_) => self.tuple_or_group(),
             Token::LSquare(_) => self.list_literal(),
             Token::LBrace(_) => self.map_literal(),
+            Token::HashLBrace(_) => self.set_literal(),
             Token::Identifier(id, _) => {
                 let res;
                 if variant_eq(self.lookahead()?, &Token::LParan(PosRange::empty())) {
@@ -634,16 +1305,31 @@ impl Parser {
                 let res = UnaryNumberNode::minus(Box::new(self.atom()?));
                 Ok(res)
             }
-            Token::Int(num, _) => {
+            Token::Tilde(_) => {
+                self.next()?;
+                let res = UnaryNumberNode::bit_not(Box::new(self.atom()?));
+                Ok(res)
+            }
+            Token::Int(num, raw, _) => {
                 let res = Node::Int(IntNode {
                     value: num.to_owned(),
+                    raw: raw.to_owned(),
+                });
+                self.next()?;
+                Ok(res)
+            }
+            Token::BigInt(num, raw, _) => {
+                let res = Node::BigInt(BigIntNode {
+                    value: num.to_owned(),
+                    raw: raw.to_owned(),
                 });
                 self.next()?;
                 Ok(res)
             }
-            Token::Double(num, _) => {
+            Token::Double(num, raw, _) => {
                 let res = Node::Double(DoubleNode {
                     value: OrderedFloat(num.to_owned()),
+                    raw: raw.to_owned(),
                 });
                 self.next()?;
                 Ok(res)
@@ -655,15 +1341,16 @@ impl Parser {
                 self.next()?;
                 Ok(res)
             }
-            Token::String(_str, _) => {
+            Token::String(_str, raw, _) => {
                 let res = Node::String(StringNode {
                     value: _str.to_owned(),
+                    raw: raw.to_owned(),
                 });
                 self.next()?;
                 Ok(res)
             }
             _ => Err(self.panic_invalid_syntax(
-                "Expected (, [, {, !, +, -, int, double, bool, str, identifier",
+                "Expected (, [, {, #{, !, +, -, ~, int, double, bool, str, identifier",
             )),
         }
     }
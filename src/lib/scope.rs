@@ -9,6 +9,8 @@ pub struct Scope {
     parent: Option<ScopePtr>,
     fn_table: HashMap<String, Rc<RefCell<FunctionDeclarationNode>>>,
     var_table: HashMap<String, Value>,
+    // Holds the symbols of functions that ended in a tail call
+    tail: bool,
 }
 
 impl Scope {
@@ -17,6 +19,7 @@ impl Scope {
             parent: Some(parent),
             fn_table: HashMap::new(),
             var_table: HashMap::new(),
+            tail: false,
         }))
     }
     pub fn from(
@@ -27,6 +30,7 @@ impl Scope {
             parent: None,
             fn_table,
             var_table,
+            tail: false,
         }))
     }
     pub fn set_parent(&mut self, parent: ScopePtr) {
@@ -64,6 +68,44 @@ impl Scope {
         self.fn_table.insert(key, value);
     }
 
+    pub fn has_function(&self, key: &String) -> bool {
+        self.fn_table.contains_key(key)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|_parent| _parent.borrow().has_function(key))
+    }
+
+    // Merges the scopes from `scope` up to `frame`, the scope of a function
+    // ending in a tail call, into one scope. A tail scope right above `frame`
+    // is merged too, so a chain of tail calls never grows the scope chain
+    // while symbols resolve the same as before
+    pub fn flatten(scope: &ScopePtr, frame: &ScopePtr) -> ScopePtr {
+        let mut chain = vec![scope.clone()];
+        while !Rc::ptr_eq(chain.last().unwrap(), frame) {
+            let parent = chain.last().unwrap().borrow().parent.clone();
+            chain.push(parent.expect("Tail call outside of its function"));
+        }
+        let mut parent = frame.borrow().parent.clone();
+        if let Some(_parent) = parent.clone().filter(|_p| _p.borrow().tail) {
+            parent = _parent.borrow().parent.clone();
+            chain.push(_parent);
+        }
+        let mut merged = Scope {
+            parent,
+            fn_table: HashMap::new(),
+            var_table: HashMap::new(),
+            tail: true,
+        };
+        // Outermost first, so inner symbols shadow outer ones
+        for _scope in chain.iter().rev() {
+            let _scope = _scope.borrow();
+            merged.fn_table.extend(_scope.fn_table.clone());
+            merged.var_table.extend(_scope.var_table.clone());
+        }
+        Rc::new(RefCell::new(merged))
+    }
+
     pub fn get_function(&self, key: &String) -> Rc<RefCell<FunctionDeclarationNode>> {
         match self.fn_table.get(key) {
             Some(val) => val.clone(),
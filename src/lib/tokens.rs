@@ -1,51 +1,70 @@
+use num_bigint::BigInt;
 use std::fmt::Display;
 
 // TokenType
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Int(i64, PosRange),           // 10
-    Double(f64, PosRange),        // 3.14
-    Boolean(bool, PosRange),      // 3.14
-    String(String, PosRange),     // "AshLang"
-    Comment(String, PosRange),    // // Comment
-    Identifier(String, PosRange), // Variables
-    Plus(PosRange),               // +
-    Minus(PosRange),              // -
-    Multiply(PosRange),           // *
-    Divide(PosRange),             // /
-    Tilde(PosRange),              // ~
-    TildeDivide(PosRange),        // ~/
-    Power(PosRange),              // ^
-    PowerDivide(PosRange),        // ^/
-    Modulus(PosRange),            // %
-    Not(PosRange),                // !
-    Equals(PosRange),             // =
-    PlusEq(PosRange),             // +=
-    MinusEq(PosRange),            // -=
-    MultiplyEq(PosRange),         // *=
-    DivideEq(PosRange),           // /=
-    TildeDivideEq(PosRange),      // ~/=
-    PowerEq(PosRange),            // ^=
-    PowerDivideEq(PosRange),      // ^/=
-    ModulusEq(PosRange),          // %=
-    DoubleEquals(PosRange),       // ==
-    NotEquals(PosRange),          // !=
-    And(PosRange),                // &
-    Or(PosRange),                 // |
-    LessThan(PosRange),           // <
-    LessThanEq(PosRange),         // <=
-    GreaterThan(PosRange),        // >
-    GreaterThanEq(PosRange),      // >=
-    LParan(PosRange),             // (
-    RParan(PosRange),             // )
-    LBrace(PosRange),             // {
-    RBrace(PosRange),             // }
-    LSquare(PosRange),            // [
-    RSquare(PosRange),            // ]
-    Comma(PosRange),              // ,
-    Colon(PosRange),              // :
-    Semicolon(PosRange),          // ;
-    EOF(PosRange),                // End of File
+    // Literals keep their source spelling, so formatting doesn't turn 1e3 into 1000
+    Int(i64, String, PosRange),       // 10
+    BigInt(BigInt, String, PosRange), // 9223372036854775808
+    Double(f64, String, PosRange),    // 3.14
+    Boolean(bool, PosRange),          // true
+    String(String, String, PosRange), // "AshLang"
+    Comment(String, PosRange),        // // Comment
+    Identifier(String, PosRange),     // Variables
+    Plus(PosRange),                   // +
+    Minus(PosRange),                  // -
+    Multiply(PosRange),               // *
+    Divide(PosRange),                 // /
+    Tilde(PosRange),                  // ~
+    TildeDivide(PosRange),            // ~/
+    Power(PosRange),                  // ^
+    PowerDivide(PosRange),            // ^/
+    Modulus(PosRange),                // %
+    Not(PosRange),                    // !
+    Equals(PosRange),                 // =
+    PlusEq(PosRange),                 // +=
+    MinusEq(PosRange),                // -=
+    MultiplyEq(PosRange),             // *=
+    DivideEq(PosRange),               // /=
+    TildeDivideEq(PosRange),          // ~/=
+    PowerEq(PosRange),                // ^=
+    PowerDivideEq(PosRange),          // ^/=
+    ModulusEq(PosRange),              // %=
+    BitAnd(PosRange),                 // .&.
+    BitOr(PosRange),                  // .|.
+    BitXor(PosRange),                 // .^.
+    ShiftLeft(PosRange),              // <<
+    ShiftRight(PosRange),             // >>
+    BitAndEq(PosRange),               // .&.=
+    BitOrEq(PosRange),                // .|.=
+    BitXorEq(PosRange),               // .^.=
+    ShiftLeftEq(PosRange),            // <<=
+    ShiftRightEq(PosRange),           // >>=
+    DoubleEquals(PosRange),           // ==
+    NotEquals(PosRange),              // !=
+    And(PosRange),                    // &
+    Or(PosRange),                     // |
+    Pipe(PosRange),                   // |>
+    Arrow(PosRange),                  // ->
+    LessThan(PosRange),               // <
+    LessThanEq(PosRange),             // <=
+    GreaterThan(PosRange),            // >
+    GreaterThanEq(PosRange),          // >=
+    LParan(PosRange),                 // (
+    RParan(PosRange),                 // )
+    LBrace(PosRange),                 // {
+    RBrace(PosRange),                 // }
+    HashLBrace(PosRange),             // #{
+    LSquare(PosRange),                // [
+    RSquare(PosRange),                // ]
+    Comma(PosRange),                  // ,
+    Colon(PosRange),                  // :
+    Semicolon(PosRange),              // ;
+    DotDot(PosRange),                 // ..
+    Dot(PosRange),                    // .
+    At(PosRange),                     // @
+    EOF(PosRange),                    // End of File
     //Keywords
     WhileK(PosRange),    // while
     IfK(PosRange),       // if
@@ -57,9 +76,17 @@ pub enum Token {
     BreakK(PosRange),    // break
     ContinueK(PosRange), // continue
     ReturnK(PosRange),   // return
+    YieldK(PosRange),    // yield
+    ForK(PosRange),      // for
+    InK(PosRange),       // in
+    LoopK(PosRange),     // loop
+    DoK(PosRange),       // do
+    StructK(PosRange),   // struct
+    TraitK(PosRange),    // trait
+    ImplK(PosRange),     // impl
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Position {
     pub line: i64,
     pub column: i64,
@@ -76,7 +103,7 @@ impl Position {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct PosRange {
     pub from: Position,
     pub to: Option<Position>,
@@ -97,10 +124,10 @@ impl PosRange {
 impl Token {
     pub fn get_name(&self) -> String {
         match self {
-            Token::Int(_, _) => "Int",
-            Token::Double(_, _) => "Double",
+            Token::Int(_, _, _) | Token::BigInt(_, _, _) => "Int",
+            Token::Double(_, _, _) => "Double",
             Token::Boolean(_, _) => "Boolean",
-            Token::String(_, _) => "String",
+            Token::String(_, _, _) => "String",
             Token::Comment(_, _) => "Comment",
             Token::Identifier(_, _) => "Identifier",
             Token::Plus(_) => "+",
@@ -120,12 +147,16 @@ impl Token {
             Token::LParan(_) => "(",
             Token::RParan(_) => ")",
             Token::LBrace(_) => "{",
+            Token::HashLBrace(_) => "#{",
             Token::RBrace(_) => "}",
             Token::LSquare(_) => "[",
             Token::RSquare(_) => "]",
             Token::Comma(_) => ",",
             Token::Colon(_) => "colon",
             Token::Semicolon(_) => ";",
+            Token::DotDot(_) => "..",
+            Token::Dot(_) => ".",
+            Token::At(_) => "@",
             Token::EOF(_) => "EOF",
             Token::WhileK(_) => "while",
             Token::IfK(_) => "if",
@@ -137,8 +168,18 @@ impl Token {
             Token::BreakK(_) => "break",
             Token::ContinueK(_) => "continue",
             Token::ReturnK(_) => "return",
+            Token::YieldK(_) => "yield",
+            Token::LoopK(_) => "loop",
+            Token::DoK(_) => "do",
+            Token::StructK(_) => "struct",
+            Token::TraitK(_) => "trait",
+            Token::ImplK(_) => "impl",
+            Token::ForK(_) => "for",
+            Token::InK(_) => "in",
             Token::And(_) => "&",
             Token::Or(_) => "|",
+            Token::Pipe(_) => "|>",
+            Token::Arrow(_) => "->",
             Token::TildeDivide(_) => "~/",
             Token::PowerDivide(_) => "^/",
             Token::PlusEq(_) => "+=",
@@ -150,16 +191,31 @@ impl Token {
             Token::PowerDivideEq(_) => "^/=",
             Token::ModulusEq(_) => "%=",
             Token::Tilde(_) => "~",
+            Token::BitAnd(_) => ".&.",
+            Token::BitOr(_) => ".|.",
+            Token::BitXor(_) => ".^.",
+            Token::ShiftLeft(_) => "<<",
+            Token::ShiftRight(_) => ">>",
+            Token::BitAndEq(_) => ".&.=",
+            Token::BitOrEq(_) => ".|.=",
+            Token::BitXorEq(_) => ".^.=",
+            Token::ShiftLeftEq(_) => "<<=",
+            Token::ShiftRightEq(_) => ">>=",
         }
         .to_string()
     }
 
     pub fn get_pos(&self) -> String {
+        self.pos_range().get_pos()
+    }
+
+    pub fn pos_range(&self) -> &PosRange {
         match self {
-            Token::Int(_, pos)
-            | Token::Double(_, pos)
+            Token::Int(_, _, pos)
+            | Token::BigInt(_, _, pos)
+            | Token::Double(_, _, pos)
             | Token::Boolean(_, pos)
-            | Token::String(_, pos)
+            | Token::String(_, _, pos)
             | Token::Comment(_, pos)
             | Token::Identifier(_, pos)
             | Token::Plus(pos)
@@ -179,12 +235,16 @@ impl Token {
             | Token::LParan(pos)
             | Token::RParan(pos)
             | Token::LBrace(pos)
+            | Token::HashLBrace(pos)
             | Token::RBrace(pos)
             | Token::LSquare(pos)
             | Token::RSquare(pos)
             | Token::Comma(pos)
             | Token::Colon(pos)
             | Token::Semicolon(pos)
+            | Token::DotDot(pos)
+            | Token::Dot(pos)
+            | Token::At(pos)
             | Token::EOF(pos)
             | Token::WhileK(pos)
             | Token::IfK(pos)
@@ -196,8 +256,18 @@ impl Token {
             | Token::BreakK(pos)
             | Token::ContinueK(pos)
             | Token::ReturnK(pos)
+            | Token::YieldK(pos)
+            | Token::LoopK(pos)
+            | Token::DoK(pos)
+            | Token::StructK(pos)
+            | Token::TraitK(pos)
+            | Token::ImplK(pos)
+            | Token::ForK(pos)
+            | Token::InK(pos)
             | Token::And(pos)
             | Token::Or(pos)
+            | Token::Pipe(pos)
+            | Token::Arrow(pos)
             | Token::TildeDivide(pos)
             | Token::PowerDivide(pos)
             | Token::PlusEq(pos)
@@ -208,15 +278,26 @@ impl Token {
             | Token::PowerEq(pos)
             | Token::PowerDivideEq(pos)
             | Token::ModulusEq(pos)
-            | Token::Tilde(pos) => pos.get_pos(),
+            | Token::BitAnd(pos)
+            | Token::BitOr(pos)
+            | Token::BitXor(pos)
+            | Token::ShiftLeft(pos)
+            | Token::ShiftRight(pos)
+            | Token::BitAndEq(pos)
+            | Token::BitOrEq(pos)
+            | Token::BitXorEq(pos)
+            | Token::ShiftLeftEq(pos)
+            | Token::ShiftRightEq(pos)
+            | Token::Tilde(pos) => pos,
         }
     }
     pub fn set_pos(&mut self, pos_range: PosRange) -> Token {
         match self {
-            Token::Int(_, pos)
-            | Token::Double(_, pos)
+            Token::Int(_, _, pos)
+            | Token::BigInt(_, _, pos)
+            | Token::Double(_, _, pos)
             | Token::Boolean(_, pos)
-            | Token::String(_, pos)
+            | Token::String(_, _, pos)
             | Token::Comment(_, pos)
             | Token::Identifier(_, pos)
             | Token::Plus(pos)
@@ -236,12 +317,16 @@ impl Token {
             | Token::LParan(pos)
             | Token::RParan(pos)
             | Token::LBrace(pos)
+            | Token::HashLBrace(pos)
             | Token::RBrace(pos)
             | Token::LSquare(pos)
             | Token::RSquare(pos)
             | Token::Comma(pos)
             | Token::Colon(pos)
             | Token::Semicolon(pos)
+            | Token::DotDot(pos)
+            | Token::Dot(pos)
+            | Token::At(pos)
             | Token::EOF(pos)
             | Token::WhileK(pos)
             | Token::IfK(pos)
@@ -253,8 +338,18 @@ impl Token {
             | Token::BreakK(pos)
             | Token::ContinueK(pos)
             | Token::ReturnK(pos)
+            | Token::YieldK(pos)
+            | Token::LoopK(pos)
+            | Token::DoK(pos)
+            | Token::StructK(pos)
+            | Token::TraitK(pos)
+            | Token::ImplK(pos)
+            | Token::ForK(pos)
+            | Token::InK(pos)
             | Token::And(pos)
             | Token::Or(pos)
+            | Token::Pipe(pos)
+            | Token::Arrow(pos)
             | Token::TildeDivide(pos)
             | Token::PowerDivide(pos)
             | Token::PlusEq(pos)
@@ -265,6 +360,16 @@ impl Token {
             | Token::PowerEq(pos)
             | Token::PowerDivideEq(pos)
             | Token::ModulusEq(pos)
+            | Token::BitAnd(pos)
+            | Token::BitOr(pos)
+            | Token::BitXor(pos)
+            | Token::ShiftLeft(pos)
+            | Token::ShiftRight(pos)
+            | Token::BitAndEq(pos)
+            | Token::BitOrEq(pos)
+            | Token::BitXorEq(pos)
+            | Token::ShiftLeftEq(pos)
+            | Token::ShiftRightEq(pos)
             | Token::Tilde(pos) => pos.set_pos(pos_range),
         };
         self.clone()
@@ -282,7 +387,7 @@ impl Position {
 }
 
 impl PosRange {
-    fn get_pos(&self) -> String {
+    pub fn get_pos(&self) -> String {
         if self == &Self::empty() {
             "".to_string()
         } else if let Some(to) = self.to.clone() {
@@ -0,0 +1,137 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{nodes::FunctionDeclarationNode, values::Value};
+
+// Value of a user declared struct, fields are kept in declaration order
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instance {
+    pub type_name: String,
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Instance {
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(_name, _)| _name == field)
+            .map(|(_, _value)| _value)
+    }
+}
+
+// A struct declaration along with the methods and traits implemented for it
+#[derive(Debug, Default)]
+pub struct UserType {
+    pub fields: Vec<String>,
+    pub methods: HashMap<String, Rc<RefCell<FunctionDeclarationNode>>>,
+    pub traits: HashSet<String>,
+}
+
+// Methods an impl must provide, and defaults it may leave out
+#[derive(Debug, Default)]
+pub struct Trait {
+    pub required: Vec<String>,
+    pub provided: Vec<FunctionDeclarationNode>,
+}
+
+// Traits the interpreter dispatches operators and printing to
+pub fn builtin_traits() -> HashMap<String, Trait> {
+    [
+        ("Display", "display"),
+        ("Eq", "eq"),
+        ("Ord", "cmp"),
+        ("Add", "add"),
+        ("Sub", "sub"),
+        ("Mul", "mul"),
+        ("Div", "div"),
+        ("Mod", "mod"),
+        ("Neg", "neg"),
+    ]
+    .into_iter()
+    .map(|(_trait, _method)| {
+        let required = vec![_method.to_string()];
+        (
+            _trait.to_string(),
+            Trait {
+                required,
+                ..Default::default()
+            },
+        )
+    })
+    .collect()
+}
+
+// Results of a memoized function by arguments. When bounded, the least
+// recently used result is dropped to make room for a new one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Memo {
+    // Result and time of last use of each cached call
+    table: HashMap<Vec<Value>, (Value, u64)>,
+    // Cached calls by time of last use, oldest first
+    order: BTreeMap<u64, Vec<Value>>,
+    clock: u64,
+    pub max: Option<usize>,
+    pub hits: u64,
+    pub misses: u64,
+    // Whether the function was reported for reading non-parameter state
+    pub warned: bool,
+}
+
+impl Memo {
+    pub fn new(max: Option<usize>) -> Self {
+        Memo {
+            max,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    pub fn get(&mut self, args: &Vec<Value>) -> Option<Value> {
+        self.clock += 1;
+        match self.table.get_mut(args) {
+            Some((_res, _used)) => {
+                self.hits += 1;
+                let key = self.order.remove(_used).unwrap();
+                *_used = self.clock;
+                self.order.insert(self.clock, key);
+                Some(_res.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, args: Vec<Value>, res: Value) {
+        self.clock += 1;
+        if let Some((_, _used)) = self.table.remove(&args) {
+            self.order.remove(&_used);
+        }
+        if self.max.is_some_and(|_max| self.table.len() >= _max) {
+            if let Some((_, _oldest)) = self.order.pop_first() {
+                self.table.remove(&_oldest);
+            }
+        }
+        self.order.insert(self.clock, args.clone());
+        self.table.insert(args, (res, self.clock));
+    }
+
+    // Drops every cached result along with the hit and miss counts
+    pub fn clear(&mut self) {
+        *self = Memo {
+            warned: self.warned,
+            ..Memo::new(self.max)
+        };
+    }
+}
@@ -14,7 +14,15 @@ pub fn is_keyword(id: &str, pos: PosRange) -> Option<Token> {
         ("let", Token::LetK(pos.clone())),
         ("break", Token::BreakK(pos.clone())),
         ("continue", Token::ContinueK(pos.clone())),
-        ("return", Token::ReturnK(pos)),
+        ("return", Token::ReturnK(pos.clone())),
+        ("yield", Token::YieldK(pos.clone())),
+        ("for", Token::ForK(pos.clone())),
+        ("in", Token::InK(pos.clone())),
+        ("loop", Token::LoopK(pos.clone())),
+        ("do", Token::DoK(pos.clone())),
+        ("struct", Token::StructK(pos.clone())),
+        ("trait", Token::TraitK(pos.clone())),
+        ("impl", Token::ImplK(pos)),
     ]);
     if keywords.contains_key(id) {
         Some(keywords.get(id).unwrap().clone())
@@ -34,6 +42,11 @@ pub fn get_assignment() -> Vec<Token> {
         Token::ModulusEq(PosRange::empty()),
         Token::TildeDivideEq(PosRange::empty()),
         Token::PowerDivideEq(PosRange::empty()),
+        Token::BitAndEq(PosRange::empty()),
+        Token::BitOrEq(PosRange::empty()),
+        Token::BitXorEq(PosRange::empty()),
+        Token::ShiftLeftEq(PosRange::empty()),
+        Token::ShiftRightEq(PosRange::empty()),
     ]
 }
 
@@ -48,6 +61,11 @@ pub fn get_assignment_from_token(tkn: &Token) -> Assignment {
         Token::ModulusEq(_) => Assignment::ModulusEq,
         Token::TildeDivideEq(_) => Assignment::TildeDivideEq,
         Token::PowerDivideEq(_) => Assignment::PowerDivideEq,
+        Token::BitAndEq(_) => Assignment::BitAndEq,
+        Token::BitOrEq(_) => Assignment::BitOrEq,
+        Token::BitXorEq(_) => Assignment::BitXorEq,
+        Token::ShiftLeftEq(_) => Assignment::ShiftLeftEq,
+        Token::ShiftRightEq(_) => Assignment::ShiftRightEq,
         _ => panic!("Invalid Assignment"),
     }
 }
@@ -1,33 +1,159 @@
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive};
 use ordered_float::OrderedFloat;
+
+use crate::iterator::IteratorPtr;
+use crate::types::Instance;
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashSet},
     fmt::Display,
     hash::{Hash, Hasher},
+    rc::Rc,
 };
+
+// Lists and Maps are shared, assigning or passing one doesn't copy it.
+// Maps keep their keys in insertion order. Map keys and Set elements are deep
+// copies that never leave the collection, so changing a List used as a key
+// can't change the hash it is stored under
+pub type ListPtr = Rc<RefCell<Vec<Value>>>;
+pub type MapPtr = Rc<RefCell<IndexMap<Value, Value>>>;
+
 // Value
 // #[derive(Debug, Clone)]
 #[derive(Debug, Clone)]
 pub enum Value {
     IntValue(i64),
+    BigIntValue(BigInt),
+    RationalValue(BigRational),
     DoubleValue(OrderedFloat<f64>),
     StringValue(String),
-    ListValue(Vec<Value>),
-    MapValue(HashMap<Value, Value>),
+    ListValue(ListPtr),
+    TupleValue(Vec<Value>),
+    MapValue(MapPtr),
+    SetValue(HashSet<Value>),
     BooleanValue(bool),
+    IteratorValue(IteratorPtr),
+    InstanceValue(Instance),
     ReturnValue(Box<Value>),
-    Break,
-    Continue,
+    // Label of the loop to break out of or continue, the innermost if None
+    Break(Option<String>),
+    Continue(Option<String>),
     None,
 }
 
+impl Value {
+    pub fn list(values: Vec<Value>) -> Value {
+        Value::ListValue(Rc::new(RefCell::new(values)))
+    }
+
+    pub fn map(entries: IndexMap<Value, Value>) -> Value {
+        Value::MapValue(Rc::new(RefCell::new(entries)))
+    }
+
+    // Copy of the value that shares nothing with it, nested Lists and Maps included
+    pub fn deep_copy(&self) -> Value {
+        match self {
+            Value::ListValue(_l) => Value::list(_l.borrow().iter().map(Value::deep_copy).collect()),
+            Value::MapValue(_m) => Value::map(
+                _m.borrow()
+                    .iter()
+                    .map(|(_k, _v)| (_k.deep_copy(), _v.deep_copy()))
+                    .collect(),
+            ),
+            Value::TupleValue(_t) => Value::TupleValue(_t.iter().map(Value::deep_copy).collect()),
+            Value::SetValue(_s) => Value::SetValue(_s.iter().map(Value::deep_copy).collect()),
+            Value::InstanceValue(_i) => Value::InstanceValue(Instance {
+                type_name: _i.type_name.clone(),
+                fields: _i
+                    .fields
+                    .iter()
+                    .map(|(_k, _v)| (_k.clone(), _v.deep_copy()))
+                    .collect(),
+            }),
+            _ => self.clone(),
+        }
+    }
+
+    // Whether this value is or holds the List or Map `target`. Map keys and Set
+    // elements are copies, so only Lists, Map values, Tuples and fields are searched
+    pub fn reaches(&self, target: &Value) -> bool {
+        match (self, target) {
+            (Value::ListValue(_l), Value::ListValue(_t)) if Rc::ptr_eq(_l, _t) => return true,
+            (Value::MapValue(_m), Value::MapValue(_t)) if Rc::ptr_eq(_m, _t) => return true,
+            _ => {}
+        }
+        match self {
+            Value::ListValue(_l) => _l.borrow().iter().any(|_v| _v.reaches(target)),
+            Value::MapValue(_m) => _m.borrow().values().any(|_v| _v.reaches(target)),
+            Value::TupleValue(_t) => _t.iter().any(|_v| _v.reaches(target)),
+            Value::InstanceValue(_i) => _i.fields.iter().any(|(_, _v)| _v.reaches(target)),
+            _ => false,
+        }
+    }
+
+    // Integers that fit in an i64 are always stored as IntValue
+    pub fn from_big_int(value: BigInt) -> Value {
+        match value.to_i64() {
+            Some(_i) => Value::IntValue(_i),
+            None => Value::BigIntValue(value),
+        }
+    }
+
+    // Rationals with a denominator of 1 are always stored as Ints
+    pub fn from_rational(value: BigRational) -> Value {
+        if value.is_integer() {
+            Value::from_big_int(value.to_integer())
+        } else {
+            Value::RationalValue(value)
+        }
+    }
+
+    // Exact rational form of a number, Doubles included
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Value::IntValue(_i) => Some(BigRational::from_integer(BigInt::from(*_i))),
+            Value::BigIntValue(_i) => Some(BigRational::from_integer(_i.clone())),
+            Value::RationalValue(_r) => Some(_r.clone()),
+            Value::DoubleValue(_d) => BigRational::from_f64(_d.0),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::IntValue(_) | Value::BigIntValue(_) => "Int",
+            Value::RationalValue(_) => "Rational",
+            Value::DoubleValue(_) => "Double",
+            Value::StringValue(_) => "String",
+            Value::ListValue(_) => "List",
+            Value::TupleValue(_) => "Tuple",
+            Value::MapValue(_) => "Map",
+            Value::SetValue(_) => "Set",
+            Value::BooleanValue(_) => "Boolean",
+            Value::IteratorValue(_) => "Iterator",
+            Value::InstanceValue(_i) => &_i.type_name,
+            Value::ReturnValue(_) => "Return",
+            Value::Break(_) => "Break",
+            Value::Continue(_) => "Continue",
+            Value::None => "None",
+        }
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::IntValue(l0), Self::IntValue(r0)) => l0 == r0,
-            (Self::DoubleValue(l0), Self::DoubleValue(r0)) => l0 == r0,
+            // Numbers are equal when their values are, whatever their types
+            _ if self.is_number() && other.is_number() => cmp_numbers(self, other).is_eq(),
             (Self::StringValue(l0), Self::StringValue(r0)) => l0 == r0,
             (Self::ListValue(l0), Self::ListValue(r0)) => l0 == r0,
+            (Self::TupleValue(l0), Self::TupleValue(r0)) => l0 == r0,
             (Self::MapValue(l0), Self::MapValue(r0)) => {
+                let (l0, r0) = (&*l0.borrow(), &*r0.borrow());
                 let mut eq = true;
                 if l0.len() != r0.len() {
                     return false;
@@ -40,33 +166,115 @@ impl PartialEq for Value {
                 }
                 eq
             }
+            (Self::SetValue(l0), Self::SetValue(r0)) => l0 == r0,
             (Self::BooleanValue(l0), Self::BooleanValue(r0)) => l0 == r0,
+            (Self::IteratorValue(l0), Self::IteratorValue(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::InstanceValue(l0), Self::InstanceValue(r0)) => l0 == r0,
             (Self::ReturnValue(l0), Self::ReturnValue(r0)) => l0 == r0,
+            (Self::Break(l0), Self::Break(r0)) => l0 == r0,
+            (Self::Continue(l0), Self::Continue(r0)) => l0 == r0,
             _ => core::mem::discriminant(self) == core::mem::discriminant(other),
         }
     }
 }
 impl Eq for Value {}
 
+impl Value {
+    fn is_number(&self) -> bool {
+        matches!(
+            self,
+            Value::IntValue(_)
+                | Value::BigIntValue(_)
+                | Value::RationalValue(_)
+                | Value::DoubleValue(_)
+        )
+    }
+
+    // Position of the value's type in the order of mixed types
+    fn rank(&self) -> u8 {
+        match self {
+            Value::None => 0,
+            Value::BooleanValue(_) => 1,
+            Value::IntValue(_)
+            | Value::BigIntValue(_)
+            | Value::RationalValue(_)
+            | Value::DoubleValue(_) => 2,
+            Value::StringValue(_) => 3,
+            Value::ListValue(_) => 4,
+            Value::TupleValue(_) => 5,
+            Value::SetValue(_) => 6,
+            Value::MapValue(_) => 7,
+            Value::InstanceValue(_) => 8,
+            Value::IteratorValue(_) => 9,
+            Value::ReturnValue(_) => 10,
+            Value::Break(_) => 11,
+            Value::Continue(_) => 12,
+        }
+    }
+}
+
+// Exact comparison of two numbers. NaN is above every other number, like in
+// OrderedFloat, so that the order stays total
+fn cmp_numbers(left: &Value, right: &Value) -> std::cmp::Ordering {
+    match (left, right) {
+        (Value::DoubleValue(l0), Value::DoubleValue(r0)) => l0.cmp(r0),
+        (Value::DoubleValue(l0), _) if !l0.is_finite() => {
+            if l0.0 == f64::NEG_INFINITY {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Greater
+            }
+        }
+        (_, Value::DoubleValue(_)) if right.to_rational().is_none() => {
+            cmp_numbers(right, left).reverse()
+        }
+        _ => left.to_rational().cmp(&right.to_rational()),
+    }
+}
+
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
+
+// Values of the same type compare by value: numbers numerically, Strings, Lists
+// and Tuples lexicographically, Sets and Maps by their sorted elements and
+// instances by type name then fields. Values of different types are ordered
+// None < Boolean < numbers < String < List < Tuple < Set < Map < instances < Iterator
 impl Ord for Value {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         match (self, other) {
             (Value::IntValue(l0), Value::IntValue(r0)) => l0.cmp(r0),
-            (Value::IntValue(l0), Value::DoubleValue(r0)) => {
-                OrderedFloat::cmp(&OrderedFloat(*l0 as f64), r0)
+            _ if self.is_number() && other.is_number() => cmp_numbers(self, other),
+            (Value::StringValue(l0), Value::StringValue(r0)) => l0.cmp(r0),
+            (Value::ListValue(l0), Value::ListValue(r0)) => l0.borrow().cmp(&r0.borrow()),
+            (Value::TupleValue(l0), Value::TupleValue(r0)) => l0.cmp(r0),
+            (Value::SetValue(l0), Value::SetValue(r0)) => {
+                let mut l0 = l0.iter().collect::<Vec<&Value>>();
+                let mut r0 = r0.iter().collect::<Vec<&Value>>();
+                l0.sort();
+                r0.sort();
+                l0.cmp(&r0)
             }
-            (Value::DoubleValue(l0), Value::IntValue(r0)) => {
-                OrderedFloat::cmp(l0, &OrderedFloat(*r0 as f64))
+            (Value::MapValue(l0), Value::MapValue(r0)) => {
+                let (l0, r0) = (l0.borrow(), r0.borrow());
+                let mut l0 = l0.iter().collect::<Vec<(&Value, &Value)>>();
+                let mut r0 = r0.iter().collect::<Vec<(&Value, &Value)>>();
+                l0.sort();
+                r0.sort();
+                l0.cmp(&r0)
             }
-            (Value::DoubleValue(l0), Value::DoubleValue(r0)) => l0.cmp(r0),
-            (Value::StringValue(l0), Value::StringValue(r0)) => l0.len().cmp(&r0.len()),
-            (Value::ListValue(l0), Value::ListValue(r0)) => l0.len().cmp(&r0.len()),
-            _ => panic!("Invalid Comparison"),
+            (Value::BooleanValue(l0), Value::BooleanValue(r0)) => l0.cmp(r0),
+            (Value::InstanceValue(l0), Value::InstanceValue(r0)) => l0.cmp(r0),
+            (Value::IteratorValue(l0), Value::IteratorValue(r0)) => {
+                Rc::as_ptr(l0).cmp(&Rc::as_ptr(r0))
+            }
+            (Value::ReturnValue(l0), Value::ReturnValue(r0)) => l0.cmp(r0),
+            (Value::Break(l0), Value::Break(r0)) | (Value::Continue(l0), Value::Continue(r0)) => {
+                l0.cmp(r0)
+            }
+            _ => self.rank().cmp(&other.rank()),
         }
     }
 }
@@ -75,53 +283,216 @@ impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
             Value::IntValue(l0) => l0.hash(state),
-            Value::DoubleValue(l0) => l0.hash(state),
+            Value::BigIntValue(l0) => l0.hash(state),
+            Value::RationalValue(l0) => l0.hash(state),
+            // Hashed like the Int or Rational it equals
+            Value::DoubleValue(l0) if l0.fract() == 0.0 && l0.abs() < i64::MAX as f64 => {
+                (l0.0 as i64).hash(state)
+            }
+            Value::DoubleValue(l0) => match self.to_rational() {
+                Some(_r) => Value::from_rational(_r).hash(state),
+                None => l0.hash(state),
+            },
             Value::StringValue(l0) => l0.hash(state),
-            Value::ListValue(l0) => l0.hash(state),
-            Value::BooleanValue(l0) => l0.hash(state),
-            Value::MapValue(l0) => {
-                for (k, v) in l0.iter() {
-                    (k, v).hash(state);
-                }
+            Value::ListValue(l0) => l0.borrow().hash(state),
+            Value::TupleValue(l0) => {
+                core::mem::discriminant(self).hash(state);
+                l0.hash(state)
             }
+            Value::BooleanValue(l0) => l0.hash(state),
+            Value::IteratorValue(l0) => Rc::as_ptr(l0).hash(state),
+            Value::InstanceValue(l0) => l0.hash(state),
+            Value::MapValue(l0) => unordered_hash(l0.borrow().iter()).hash(state),
+            Value::SetValue(l0) => unordered_hash(l0.iter()).hash(state),
             _ => core::mem::discriminant(self).hash(state),
         }
     }
 }
 
+// Hash of a collection that doesn't depend on its iteration order
+pub fn unordered_hash<T: Hash>(items: impl Iterator<Item = T>) -> u64 {
+    items
+        .map(|item| {
+            let mut hasher = DefaultHasher::new();
+            item.hash(&mut hasher);
+            hasher.finish()
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+// Lets the caller write instances its own way, None falls back to the fields
+pub type ShowFn<'a> = dyn FnMut(&Value) -> Option<String> + 'a;
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(false, &mut |_| None))
+    }
+}
+
+impl Value {
+    // The value as it is written in code, Strings quoted and Doubles with a decimal point
+    pub fn repr(&self) -> String {
+        self.render(true, &mut |_| None)
+    }
+
+    // Text of the value, elements of collections are always written in repr
+    pub fn render(&self, repr: bool, show: &mut ShowFn) -> String {
+        if let Some(_s) = show(self) {
+            return _s;
+        }
         match self {
-            Value::IntValue(i) => i.fmt(f),
-            Value::DoubleValue(d) => d.fmt(f),
-            Value::StringValue(s) => s.fmt(f),
+            Value::IntValue(i) => i.to_string(),
+            Value::BigIntValue(i) => i.to_string(),
+            Value::RationalValue(r) => r.to_string(),
+            Value::DoubleValue(d) if repr && d.is_finite() && d.fract() == 0.0 => format!("{d}.0"),
+            Value::DoubleValue(d) => d.to_string(),
+            Value::StringValue(s) if repr => quote(s),
+            Value::StringValue(s) => s.clone(),
             Value::ListValue(l) => {
-                write!(f, "[",)?;
+                format!("[{}]", render_all(l.borrow().iter(), show).join(", "))
+            }
+            Value::MapValue(m) => format!(
+                "{{{}}}",
+                m.borrow()
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k.render(true, show), v.render(true, show)))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Value::TupleValue(t) if t.len() == 1 => format!("({},)", t[0].render(true, show)),
+            Value::TupleValue(t) => format!("({})", render_all(t.iter(), show).join(", ")),
+            Value::SetValue(s) => format!("#{{{}}}", render_all(sorted(s), show).join(", ")),
+            Value::BooleanValue(b) => b.to_string(),
+            Value::IteratorValue(_) => String::from("<Iterator>"),
+            Value::InstanceValue(i) => format!(
+                "{}({})",
+                i.type_name,
+                i.fields
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v.render(true, show)))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Value::ReturnValue(r) => r.render(repr, show),
+            Value::Break(_) => String::from("Break"),
+            Value::Continue(_) => String::from("Continue"),
+            Value::None => String::from("None"),
+        }
+    }
 
-                if !l.is_empty() {
-                    write!(f, "{}", l[0])?;
-                }
-                if l.len() > 1 {
-                    l.iter().skip(1).try_for_each(|e| write!(f, ", {}", e))?;
-                }
-                write!(f, "]",)
+    // repr that breaks collections which don't fit in `width` columns
+    // into one element per line
+    pub fn pretty(&self, width: usize, show: &mut ShowFn) -> String {
+        let mut out = String::new();
+        self.pretty_at(0, 0, width, show, &mut out);
+        out
+    }
+
+    // `indent` is the indentation of the current line and `col` the column the value starts at
+    fn pretty_at(
+        &self,
+        indent: usize,
+        col: usize,
+        width: usize,
+        show: &mut ShowFn,
+        out: &mut String,
+    ) {
+        let flat = self.render(true, show);
+        // Instances shown through Display are never broken into their fields
+        let displayed = matches!(self, Value::InstanceValue(_)) && show(self).is_some();
+        if col + flat.chars().count() <= width || displayed {
+            out.push_str(&flat);
+            return;
+        }
+        let (open, close, items): (String, &str, Vec<(String, Value)>) = match self {
+            Value::ListValue(l) => (
+                String::from("["),
+                "]",
+                l.borrow()
+                    .iter()
+                    .map(|v| (String::new(), v.clone()))
+                    .collect(),
+            ),
+            Value::TupleValue(t) => (
+                String::from("("),
+                ")",
+                t.iter().map(|v| (String::new(), v.clone())).collect(),
+            ),
+            Value::SetValue(s) => (
+                String::from("#{"),
+                "}",
+                sorted(s).map(|v| (String::new(), v.clone())).collect(),
+            ),
+            Value::MapValue(m) => (
+                String::from("{"),
+                "}",
+                m.borrow()
+                    .iter()
+                    .map(|(k, v)| (format!("{}: ", k.render(true, show)), v.clone()))
+                    .collect(),
+            ),
+            Value::InstanceValue(i) => (
+                format!("{}(", i.type_name),
+                ")",
+                i.fields
+                    .iter()
+                    .map(|(k, v)| (format!("{k}: "), v.clone()))
+                    .collect(),
+            ),
+            _ => {
+                out.push_str(&flat);
+                return;
             }
-            Value::MapValue(m) => {
-                write!(f, "{{",)?;
-                let len = m.len();
-                for (i, (k, v)) in m.iter().enumerate() {
-                    write!(f, "{}:{}", k, v)?;
-                    if i != len - 1 {
-                        write!(f, ", ",)?;
-                    }
-                }
-                write!(f, "}}",)
+        };
+        if items.is_empty() {
+            out.push_str(&flat);
+            return;
+        }
+        // A Tuple of one keeps its trailing comma
+        let single = matches!(self, Value::TupleValue(t) if t.len() == 1);
+        out.push_str(&open);
+        let inner = indent + 2;
+        for (i, (prefix, value)) in items.iter().enumerate() {
+            out.push('\n');
+            out.push_str(&" ".repeat(inner));
+            out.push_str(prefix);
+            value.pretty_at(inner, inner + prefix.chars().count(), width, show, out);
+            if i != items.len() - 1 || single {
+                out.push(',');
             }
-            Value::BooleanValue(b) => b.fmt(f),
-            Value::ReturnValue(r) => write!(f, "{}", *r),
-            Value::Break => write!(f, "Break"),
-            Value::Continue => write!(f, "Continue"),
-            Value::None => write!(f, "None"),
+        }
+        out.push('\n');
+        out.push_str(&" ".repeat(indent));
+        out.push_str(close);
+    }
+}
+
+fn render_all<'a>(values: impl Iterator<Item = &'a Value>, show: &mut ShowFn) -> Vec<String> {
+    values.map(|v| v.render(true, show)).collect()
+}
+
+// Elements of a Set in order, so that it prints the same every time
+fn sorted(set: &HashSet<Value>) -> impl Iterator<Item = &Value> {
+    let mut values = set.iter().collect::<Vec<&Value>>();
+    values.sort();
+    values.into_iter()
+}
+
+// String literal that reads back as `text`
+fn quote(text: &str) -> String {
+    let mut res = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            '\0' => res.push_str("\\0"),
+            _ if c.is_control() => res.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            _ => res.push(c),
         }
     }
+    res.push('"');
+    res
 }
@@ -1,6 +1,8 @@
 use std::env::args;
 use std::fs;
+use std::process;
 
+use ash_lang::checker::Checker;
 use ash_lang::formatter::Formatter;
 use ash_lang::interpreter::Interpreter;
 use ash_lang::lexer::Lexer;
@@ -9,26 +11,15 @@ use ash_lang::parser::Parser;
 fn main() -> Result<(), String> {
     let args = args().collect::<Vec<String>>();
 
-    let cmd;
-    let file;
-    if args.len() == 2 {
-        cmd = "run".to_string();
-        file = args
-            .get(1)
-            .expect("Expected command or file as second argument")
-            .to_string();
-    } else if args.len() == 3 {
-        cmd = args
-            .get(1)
-            .expect("Expected command as second argument")
-            .to_string();
-        file = args
-            .get(2)
-            .expect("Expected file as third argument")
-            .to_string();
-    } else {
-        return Err("Invalid Arguments".to_string());
-    }
+    // ash_lang [command] <file> [args...], args after the file are passed to main
+    let commands = ["run", "analyze", "fmt", "check"];
+    let (cmd, rest) = match args.get(1) {
+        Some(_cmd) if commands.contains(&_cmd.as_str()) => (_cmd.to_string(), &args[2..]),
+        Some(_) => ("run".to_string(), &args[1..]),
+        None => return Err("Invalid Arguments".to_string()),
+    };
+    let file = rest.first().ok_or("Expected file as argument")?;
+    let script_args = rest[1..].to_vec();
 
     let code = fs::read_to_string(file).map_err(|x| format!("{}", x))?;
 
@@ -38,11 +29,12 @@ fn main() -> Result<(), String> {
         let mut parser = Parser::new(tokens);
         let ast = parser.parse()?;
 
-        // Executes the Code
-        let mut interpreter = Interpreter::new(ast);
-        interpreter.eval();
-
-        return Ok(());
+        // Executes the Code, an Int returned by main is the exit code
+        let mut interpreter = Interpreter::new(ast).with_args(script_args);
+        match interpreter.run() {
+            0 => return Ok(()),
+            _code => process::exit(_code),
+        }
     } else if cmd == *"analyze" {
         // Analyzes the Code
         let mut lexer = Lexer::new(code);
@@ -50,6 +42,21 @@ fn main() -> Result<(), String> {
         let mut parser = Parser::new(tokens);
         let _ = parser.parse()?;
 
+        return Ok(());
+    } else if cmd == *"check" {
+        // Type checks the Code without running it
+        let mut lexer = Lexer::new(code);
+        let tokens = lexer.tokenize()?;
+        let mut parser = Parser::new(tokens);
+        let ast = parser.parse()?;
+        let errors = Checker::new().check(&ast);
+
+        for error in errors.iter() {
+            println!("{}", error);
+        }
+        if !errors.is_empty() {
+            return Err(format!("{} type error(s)", errors.len()));
+        }
         return Ok(());
     } else if cmd == *"fmt" {
         let mut lexer = Lexer::new(code);